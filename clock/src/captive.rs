@@ -0,0 +1,64 @@
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::Result;
+use drivers::dns::{build_response, on_subnet};
+
+use log::info;
+
+const DNS_PORT: u16 = 53;
+const STACK_SIZE: usize = 4096;
+/// Largest DNS message sent over UDP without extensions.
+const MAX_LEN: usize = 512;
+
+/// Answers every DNS lookup with `address` while `enabled` is set, so phones
+/// on the setup access point find the config page. It only listens on the
+/// access point, at `address`, and drops queries from outside its network
+/// with `prefix_len` bits, or while `enabled` is not set.
+pub fn spawn_dns_server(
+    address: Ipv4Addr,
+    prefix_len: u8,
+    enabled: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>> {
+    let socket = UdpSocket::bind((address, DNS_PORT))?;
+    info!("DNS server listening on {}:{}", address, DNS_PORT);
+
+    let handle = std::thread::Builder::new()
+        .name("dns".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut query = [0; MAX_LEN];
+            let mut response = [0; MAX_LEN];
+            loop {
+                let (len, peer) = match socket.recv_from(&mut query) {
+                    Ok(received) => received,
+                    Err(e) => {
+                        info!("Error receiving DNS query: {:?}", e);
+                        std::thread::sleep(Duration::from_secs(1));
+                        continue;
+                    }
+                };
+                if !enabled.load(Ordering::Relaxed) {
+                    continue;
+                }
+                match peer.ip() {
+                    IpAddr::V4(ip) if on_subnet(ip, address, prefix_len) => {}
+                    _ => continue,
+                }
+
+                match build_response(&query[..len], address, &mut response) {
+                    Ok(len) => {
+                        if let Err(e) = socket.send_to(&response[..len], peer) {
+                            info!("Error sending DNS response: {:?}", e);
+                        }
+                    }
+                    Err(e) => info!("Ignoring DNS query from {}: {}", peer, e),
+                }
+            }
+        })?;
+
+    Ok(handle)
+}
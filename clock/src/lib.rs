@@ -1,4 +1,8 @@
+pub mod captive;
+pub mod mdns;
 pub mod rgb_led;
+pub mod secrets;
 pub mod server;
+pub mod status;
 pub mod storage;
 pub mod wifi;
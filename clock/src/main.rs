@@ -1,31 +1,58 @@
 use drivers::nixie_display::HourFormat;
 use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
-use esp_idf_svc::{eventloop::EspSystemEventLoop, sntp, timer::EspTaskTimerService};
+use esp_idf_svc::{eventloop::EspSystemEventLoop, timer::EspTaskTimerService};
 use std::{
-    sync::{mpsc::channel, Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::channel,
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use chrono::Utc;
 use chrono_tz::Tz;
 
 use drivers::{
+    api::{AppState, ScanRequest},
     config::{ConfigStorage, InternalConfig, DEFAULT_CONFIG},
     debouncer::Debouncer,
-    nixie_display::NixieDisplay,
+    events::{ClockEvent, EventBus},
+    long_press::{LongPress, PressState},
+    nixie_display::{NixieDisplay, OverrideStack},
     rgb_led::RgbLed,
+    secrets::{ChaChaCipher, StoredKey},
     shift_register::ShiftRegister,
+    status::{DeviceStatus, NetworkStatus, TimeStatus},
     storage::{InMemoryStorage, Storage},
+    wifi::{
+        generate_ap_password, nearby_networks, AccessPoint, ChangeResult, ConnectionManager,
+        ConnectionState, CredentialTrial, RetryPolicy, TrialDecision, WifiStatus,
+    },
 };
 use embedded_hal::digital::InputPin;
 use esp_idf_svc::hal::{gpio::*, prelude::*};
 use esp_idf_svc::nvs::{EspCustomNvsPartition, EspDefaultNvsPartition};
+use nixie_clock_rust::secrets::secrets_protected;
 use nixie_clock_rust::storage::NvsStorage;
 
-use log::info;
+use log::{info, warn};
+use nixie_clock_rust::captive::spawn_dns_server;
+use nixie_clock_rust::mdns::MdnsAnnouncer;
 use nixie_clock_rust::rgb_led::create_driver;
 use nixie_clock_rust::server::create_server;
-use nixie_clock_rust::wifi::configure_wifi;
+use nixie_clock_rust::status::{firmware, start_sntp, sync_state, system_status, LastSync};
+use nixie_clock_rust::wifi::EspWifiDriver;
+
+/// NVS partition the settings are kept on, separate from the WiFi driver's.
+const CONFIG_PARTITION: &str = "config";
+const FACTORY_RESET_HOLD: Duration = Duration::from_secs(10);
+/// How long new WiFi settings get to connect before the old ones come back.
+const WIFI_TRIAL_TIMEOUT: Duration = Duration::from_secs(90);
+/// How long the access point follows the connection with a delay.
+const AP_GRACE: Duration = Duration::from_secs(120);
+/// How long the generated access point password is shown at first boot.
+const AP_PASSWORD_DISPLAY: Duration = Duration::from_secs(30);
 
 fn main() -> anyhow::Result<()> {
     // It is necessary to call this function once. Otherwise some patches to the runtime
@@ -42,12 +69,28 @@ fn main() -> anyhow::Result<()> {
     let modem = peripherals.modem;
     let ledc = peripherals.ledc;
 
-    let storage: Box<dyn Storage + Send> = match EspCustomNvsPartition::take("config") {
-        Ok(partition) => Box::new(NvsStorage::new(partition, "storage")?),
+    let storage: Box<dyn Storage + Send> = match EspCustomNvsPartition::take(CONFIG_PARTITION) {
+        Ok(partition) => Box::new(NvsStorage::new(partition, CONFIG_PARTITION, "storage")?),
         Err(_) => Box::new(InMemoryStorage::new()),
     };
 
-    let config_storage = Arc::new(Mutex::new(ConfigStorage::new(storage)));
+    // The key lives on the default partition, which NVS encryption covers.
+    // It is stored before anything is sealed with it.
+    let nvs = EspDefaultNvsPartition::take()?;
+    let mut key_storage = NvsStorage::new(nvs.clone(), "nvs", "keys")?;
+    let key = match StoredKey::load(&key_storage)? {
+        Some(key) => key,
+        None => {
+            let key = StoredKey::generate()?;
+            key.store(&mut key_storage)?;
+            key
+        }
+    };
+    if !secrets_protected() {
+        warn!("NVS encryption is off: stored passwords can be read from a flash dump");
+    }
+    let cipher = Box::new(ChaChaCipher::new(&key)?);
+    let config_storage = Arc::new(Mutex::new(ConfigStorage::new(storage, cipher)));
 
     // Create the shift register
     let mut data_pin = PinDriver::output(pins.gpio16)?;
@@ -82,7 +125,42 @@ fn main() -> anyhow::Result<()> {
     // Let it trigger every second
     callback_timer.every(Duration::from_millis(10))?;
 
-    let app_config = config_storage.lock().unwrap().load()?;
+    // Holding the button while powering up counts down on the tubes and then
+    // wipes the saved settings. Give the debouncer time to settle first.
+    std::thread::sleep(Duration::from_millis(200));
+    let mut reset_press = LongPress::new(FACTORY_RESET_HOLD);
+    loop {
+        let pressed = button_debouncer.lock().unwrap().is_low().unwrap();
+        match reset_press.update(pressed, Instant::now()) {
+            PressState::Released => break,
+            PressState::Holding { remaining_secs } => {
+                let secs = remaining_secs.min(99) as u8;
+                display.show_digits(&[0, 0, secs / 10, secs % 10]);
+            }
+            PressState::Triggered => {
+                info!("Factory reset requested at boot");
+                config_storage.lock().unwrap().factory_reset()?;
+                display.show_digits(&[0, 0, 0, 0]);
+                // Wait for release so the press doesn't also change display mode
+                while button_debouncer.lock().unwrap().is_low().unwrap() {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                break;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut app_config = config_storage.lock().unwrap().load()?;
+    // Shown on the tubes by the main loop for a while after starting up
+    let mut new_ap_password: Option<Vec<u8>> = None;
+    if app_config.ap_pass().is_empty() {
+        // First boot, give the access point a password of its own
+        app_config = app_config.with_ap_pass(&generate_ap_password()?);
+        config_storage.lock().unwrap().save(&app_config)?;
+        new_ap_password = Some(app_config.ap_pass().bytes().map(|b| b - b'0').collect());
+    }
+    let booted = Instant::now();
     info!("Setting led color to: #{:06x}", app_config.led_color());
     let hour_format = if app_config.hours_24() { HourFormat::TwentyFourHour } else { HourFormat::TwelveHour };
     display.set_hour_format(hour_format);
@@ -93,55 +171,186 @@ fn main() -> anyhow::Result<()> {
     let sys_loop: esp_idf_svc::eventloop::EspEventLoop<esp_idf_svc::eventloop::System> =
         EspSystemEventLoop::take()?;
 
-    let nvs = EspDefaultNvsPartition::take()?;
-
+    let mut access_point = AccessPoint::new(app_config.ap_mode(), AP_GRACE);
     let mut esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs.clone()))?;
-    let mut wifi: BlockingWifi<&mut EspWifi<'_>> =
-        BlockingWifi::wrap(&mut esp_wifi, sys_loop.clone())?;
+    let mut wifi = EspWifiDriver::new(
+        BlockingWifi::wrap(&mut esp_wifi, sys_loop.clone())?,
+        &sys_loop,
+        &default_config,
+        access_point.is_enabled(),
+    )?;
 
-    if let Err(e) = configure_wifi(&mut wifi, &app_config, &default_config) {
+    if let Err(e) = wifi.configure(&app_config) {
         info!("Error configuring wifi: {:?}", e);
     }
+    let mac = wifi.mac()?;
+    let mut mdns = match MdnsAnnouncer::new(&app_config.hostname_or_default(&mac)) {
+        Ok(mdns) => Some(mdns),
+        Err(e) => {
+            info!("Error starting mDNS: {:?}", e);
+            None
+        }
+    };
+    let captive_portal = Arc::new(AtomicBool::new(access_point.is_enabled()));
+    // The portal is a convenience, so the clock runs on without it
+    if let Err(e) = spawn_dns_server(
+        wifi.ap_address(),
+        wifi.ap_prefix_len(),
+        captive_portal.clone(),
+    ) {
+        info!("Error starting captive portal DNS server: {:?}", e);
+    }
+    let mut connection = ConnectionManager::new(RetryPolicy::default());
+    connection.start(&mut wifi, app_config.candidate_networks(), Instant::now());
     // Keep it around or else the SNTP service will stop
-    let mut _sntp = sntp::EspSntp::new_default()?;
+    let last_sync = LastSync::default();
+    let mut _sntp = start_sntp(last_sync.clone())?;
     info!("SNTP initialized");
     let (tx, rx) = channel::<InternalConfig>();
-    let mut _server = create_server(config_storage.clone(), tx.clone())?;
+    let wifi_status = Arc::new(Mutex::new(WifiStatus::default()));
+    let device_status = Arc::new(Mutex::new(DeviceStatus::default()));
+    let events = Arc::new(EventBus::default());
+    let overrides = Arc::new(Mutex::new(OverrideStack::default()));
+    let mut led_color = app_config.led_color();
+    events.publish(ClockEvent::Led {
+        color: app_config.led_color(),
+    });
+    let firmware = firmware();
+    info!("Firmware {} ({})", firmware.version, firmware.git_hash);
+    let (scan_tx, scan_rx) = channel::<ScanRequest>();
+    let app_state = AppState::new(
+        config_storage.clone(),
+        tx.clone(),
+        wifi_status.clone(),
+        device_status.clone(),
+        events.clone(),
+        overrides.clone(),
+        scan_tx,
+    )
+    .with_ap_address(wifi.ap_address())
+    .with_captive_portal(captive_portal.clone());
+    let mut _server = create_server(app_state)?;
 
+    let mut current = app_config.clone();
+    let mut trial: Option<CredentialTrial> = None;
+    let mut scan_replies: Vec<ScanRequest> = Vec::new();
     let mut tz: Tz = app_config.tz().parse().unwrap();
     info!("Time Zone: {:?}", tz);
 
     let mut counter = 0;
     loop {
+        let mut network_change = None;
+
         if let Ok(config) = rx.try_recv() {
             info!("Received new config: {:?}", config);
-            rgb.set_color(config.led_color())?;
             tz = config.tz().parse().unwrap();
+            access_point.set_mode(config.ap_mode());
+
+            let hostname = config.hostname_or_default(&mac);
+            if let Err(e) = wifi.set_hostname(&hostname) {
+                info!("Error setting DHCP hostname: {:?}", e);
+            }
+            if let Some(Err(e)) = mdns.as_mut().map(|mdns| mdns.set_hostname(&hostname)) {
+                info!("Error setting mDNS hostname: {:?}", e);
+            }
 
-            let wifi_config = wifi.get_configuration()?;
-            let client_config = wifi_config.as_client_conf_ref().unwrap();
-            if client_config.ssid != config.wifi_ssid()
-                || client_config.password != config.wifi_pass()
-            {
-                wifi.stop()?;
-
-                if let Err(e) = configure_wifi(&mut wifi, &config, &default_config) {
-                    info!("Error configuring wifi: {:?}", e);
-                } else {
-                    drop(_sntp);
-                    _sntp = sntp::EspSntp::new_default()?;
+            if !config.same_network_settings(&current) {
+                // Try the new settings, holding on to the last ones that
+                // connected in case they don't work
+                trial = CredentialTrial::begin(
+                    &current,
+                    connection.state(),
+                    trial.take(),
+                    WIFI_TRIAL_TIMEOUT,
+                    Instant::now(),
+                );
+                wifi_status.lock().unwrap().last_change =
+                    trial.as_ref().map(|_| ChangeResult::Pending);
+                network_change = Some(config.clone());
+            }
+            current = config;
+        }
+
+        if let Some(running) = &trial {
+            match running.decide(connection.state(), Instant::now()) {
+                TrialDecision::Wait => {}
+                TrialDecision::Keep => {
+                    info!("New WiFi settings connected");
+                    trial = None;
+                    wifi_status.lock().unwrap().last_change = Some(ChangeResult::Applied);
+                }
+                TrialDecision::RollBack => {
+                    info!("New WiFi settings did not connect, restoring previous settings");
+                    current = trial.take().unwrap().restore(&current);
+                    if let Err(e) = config_storage.lock().unwrap().save(&current) {
+                        info!("Error saving restored config: {:?}", e);
+                    }
+                    wifi_status.lock().unwrap().last_change = Some(ChangeResult::RolledBack);
+                    events.publish(ClockEvent::ConfigChanged);
+                    network_change = Some(current.clone());
                 }
             }
         }
 
+        if let Some(config) = network_change {
+            if let Err(e) = wifi.configure(&config) {
+                info!("Error configuring wifi: {:?}", e);
+            }
+            connection.start(&mut wifi, config.candidate_networks(), Instant::now());
+            // The trial is only decided from here on, once the connection
+            // state is about the new settings
+            if let Some(running) = &mut trial {
+                running.connection_restarted();
+            }
+        }
+
+        let was_connected = matches!(connection.state(), ConnectionState::Connected { .. });
+        let state = connection.poll(&mut wifi, Instant::now());
+        if !was_connected && matches!(state, ConnectionState::Connected { .. }) {
+            // Sync the time as soon as a connection comes up
+            drop(_sntp);
+            _sntp = start_sntp(last_sync.clone())?;
+        }
+        wifi_status.lock().unwrap().connection = state.clone();
+
+        // Scans run alongside the connection, which answers them all once done
+        while let Ok(reply) = scan_rx.try_recv() {
+            connection.scan(&mut wifi, Instant::now());
+            scan_replies.push(reply);
+        }
+        if !connection.is_scanning() {
+            for reply in scan_replies.drain(..) {
+                let _ = reply.send(nearby_networks(connection.scan_results().to_vec()));
+            }
+        }
+
+        let ap_enabled = access_point.update(state, Instant::now());
+        if let Err(e) = wifi.set_access_point(ap_enabled) {
+            info!("Error switching access point: {:?}", e);
+        }
+        captive_portal.store(ap_enabled, Ordering::Relaxed);
+
         if button_debouncer.lock().unwrap().is_low().unwrap() {
             counter = (counter + 1) % 5;
         } else {
             counter = 0;
         }
 
+        if booted.elapsed() >= AP_PASSWORD_DISPLAY {
+            new_ap_password = None;
+        }
+        let mut shown_override = overrides.lock().unwrap().current(Instant::now()).cloned();
         if counter == 1 {
-            display.next_mode();
+            // A press dismisses the password and overrides before it changes
+            // the mode
+            if new_ap_password.is_some() {
+                new_ap_password = None;
+            } else if shown_override.is_some() {
+                overrides.lock().unwrap().clear();
+                shown_override = None;
+            } else {
+                display.next_mode();
+            }
         }
 
         // To get a better formatting of the time, you can use the `chrono` or `time` Rust crates
@@ -151,7 +360,54 @@ fn main() -> anyhow::Result<()> {
             local_time,
             button_debouncer.lock().unwrap().is_low().unwrap()
         );
-        display.display(local_time);
+        match (&new_ap_password, &shown_override) {
+            (Some(digits), _) => {
+                // Four digits at a time, switching every two seconds
+                let halves: Vec<&[u8]> = digits.chunks(4).collect();
+                let half = (booted.elapsed().as_secs() / 2) as usize % halves.len();
+                display.show_digits(halves[half]);
+            }
+            (None, Some(shown)) => display.show_override(shown),
+            (None, None) => display.display(local_time),
+        }
+
+        let color = shown_override
+            .and_then(|shown| shown.led_color)
+            .unwrap_or(current.led_color());
+        if color != led_color {
+            rgb.set_color(color)?;
+            led_color = color;
+            events.publish(ClockEvent::Led { color });
+        }
+        // The password is only for whoever can see the tubes
+        if new_ap_password.is_none() {
+            events.publish(ClockEvent::Display {
+                frame: display.frame().clone(),
+                mode: display.mode(),
+            });
+        }
+
+        let connected_ssid = match state {
+            ConnectionState::Connected { ssid } => Some(ssid.clone()),
+            _ => None,
+        };
+        let connected = connected_ssid.is_some();
+        *device_status.lock().unwrap() = DeviceStatus::new(
+            TimeStatus {
+                local_time: local_time.fixed_offset(),
+                time_zone: tz.name().to_string(),
+                sync: sync_state(_sntp.get_sync_status()),
+                last_sync: *last_sync.lock().unwrap(),
+            },
+            NetworkStatus {
+                ssid: connected_ssid,
+                rssi: if connected { wifi.rssi() } else { None },
+                ip: if connected { wifi.ip() } else { None },
+                ap_enabled,
+            },
+            system_status(),
+            firmware.clone(),
+        );
 
         std::thread::sleep(std::time::Duration::from_millis(200));
     }
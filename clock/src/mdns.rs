@@ -0,0 +1,38 @@
+use anyhow::Result;
+use esp_idf_svc::mdns::EspMdns;
+
+use log::info;
+
+const INSTANCE_NAME: &str = "Nixie Clock";
+const HTTP_PORT: u16 = 80;
+
+/// Announces the clock as `<hostname>.local`, along with its config page as
+/// an `_http._tcp` service.
+pub struct MdnsAnnouncer {
+    mdns: EspMdns,
+    hostname: String,
+}
+
+impl MdnsAnnouncer {
+    pub fn new(hostname: &str) -> Result<Self> {
+        let mut mdns = EspMdns::take()?;
+        mdns.set_hostname(hostname)?;
+        mdns.set_instance_name(INSTANCE_NAME)?;
+        mdns.add_service(None, "_http", "_tcp", HTTP_PORT, &[("path", "/")])?;
+        info!("Announcing {}.local", hostname);
+
+        Ok(MdnsAnnouncer {
+            mdns,
+            hostname: hostname.to_string(),
+        })
+    }
+
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<()> {
+        if hostname != self.hostname {
+            self.mdns.set_hostname(hostname)?;
+            self.hostname = hostname.to_string();
+            info!("Announcing {}.local", hostname);
+        }
+        Ok(())
+    }
+}
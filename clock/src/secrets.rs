@@ -0,0 +1,9 @@
+use esp_idf_svc::sys;
+
+/// Whether the secrets key is out of reach of a flash dump. That takes NVS
+/// encryption, whose own keys rely on flash encryption.
+#[allow(unexpected_cfgs)]
+pub fn secrets_protected() -> bool {
+    // SAFETY: only reads the eFuse flag
+    cfg!(esp_idf_nvs_encryption) && unsafe { sys::esp_flash_encryption_enabled() }
+}
@@ -1,90 +1,180 @@
-use std::sync::{mpsc::Sender, Arc, Mutex};
+use std::ffi::CString;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use embedded_svc::{
-    http::{Headers, Method},
+    http::{Headers, Method as EspMethod},
     io::{Read, Write},
 };
 
-use drivers::config::{Config, ConfigStorage, InternalConfig};
-use esp_idf_svc::http::server::EspHttpServer;
-
+use drivers::api::{self, AppState};
+use drivers::events::{Subscription, KEEP_ALIVE, KEEP_ALIVE_INTERVAL};
+use drivers::http::{
+    ApiError, ErrorCode, Method, Request, Response, MAX_BODY_LEN, REQUEST_HEADERS,
+};
+use esp_idf_svc::hal::reset::restart;
+use esp_idf_svc::http::server::{EspHttpConnection, EspHttpServer, Request as EspRequest};
+use esp_idf_svc::sys::{self, esp, httpd_req_t, EspError};
 use log::info;
 
 const STACK_SIZE: usize = 10240;
-const MAX_LEN: usize = 256;
+const EVENT_STREAM_STACK_SIZE: usize = 4096;
 static INDEX_HTML: &str = include_str!("../../webapp/dist/index.html");
 
-pub fn create_server(
-    config_storage: Arc<Mutex<ConfigStorage>>,
-    sender: Sender<InternalConfig>,
-) -> Result<EspHttpServer<'static>, anyhow::Error> {
+/// Serves the API from `drivers::api`, with the config page and restarts
+/// that only the device can provide.
+pub fn create_server(state: AppState) -> Result<EspHttpServer<'static>, anyhow::Error> {
     let server_configuration = esp_idf_svc::http::server::Configuration {
         stack_size: STACK_SIZE,
         ..Default::default()
     };
     let mut server = EspHttpServer::new(&server_configuration)?;
-    server.fn_handler("/", Method::Get, |req| {
-        req.into_ok_response()?
-            .write_all(INDEX_HTML.as_bytes())
-            .map(|_| ())
-    })?;
-    let storage2 = config_storage.clone();
-    server.fn_handler("/config", Method::Get, move |req| {
-        let mut s = storage2.lock().unwrap();
-        let config = s.load().unwrap();
-        let j = serde_json::to_string(&Config::from(config)).unwrap();
-
-        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
-            .write_all(j.as_bytes())
-            .map(|_| ())
+    let state = Arc::new(state.with_index_html(INDEX_HTML).with_restart(restart_soon));
+    let router = Arc::new(api::router());
+
+    for (method, path) in router.routes() {
+        let state = state.clone();
+        let handlers = router.clone();
+        server.fn_handler::<anyhow::Error, _>(path, esp_method(method), move |mut req| {
+            let response = match read_request(&mut req, method) {
+                Ok(request) => handlers.handle(&request, &state),
+                Err(e) => Response::from(e),
+            };
+            write_response(req, &response)
+        })?;
+    }
+
+    server.fn_handler::<anyhow::Error, _>("/events", EspMethod::Get, move |req| {
+        match api::subscribe_events(&state) {
+            Ok((response, events)) => stream_events(req, response, events),
+            Err(e) => write_response(req, &Response::from(e)),
+        }
     })?;
-    let storage3 = config_storage.clone();
-    server.fn_handler::<anyhow::Error, _>("/config", Method::Post, move |mut req| {
-        let len = req.content_len().unwrap_or(0) as usize;
-        let mut s = storage3.lock().unwrap();
-
-        if len > MAX_LEN {
-            req.into_status_response(413)?
-                .write_all("Request too big".as_bytes())?;
-            return Ok(());
+    Ok(server)
+}
+
+/// Copies a request out of the HTTP server, refusing bodies over
+/// `MAX_BODY_LEN`.
+fn read_request(
+    req: &mut EspRequest<&mut EspHttpConnection>,
+    method: Method,
+) -> Result<Request, ApiError> {
+    let mut request = Request::new(method, req.uri());
+    for name in REQUEST_HEADERS {
+        if let Some(value) = req.header(name) {
+            request = request.with_header(name, value);
         }
+    }
+
+    let len = req.content_len().unwrap_or(0) as usize;
+    if len > MAX_BODY_LEN {
+        return Err(ApiError::new(
+            ErrorCode::TooLarge,
+            "request body is too big",
+        ));
+    }
+    let mut body = vec![0; len];
+    req.read_exact(&mut body)
+        .map_err(|_| ApiError::new(ErrorCode::BadRequest, "error reading request body"))?;
+    Ok(request.with_body(&body))
+}
+
+fn write_response(req: EspRequest<&mut EspHttpConnection>, response: &Response) -> Result<()> {
+    let headers: Vec<_> = response.headers().collect();
+    req.into_response(response.status(), None, &headers)?
+        .write_all(response.body())?;
+    Ok(())
+}
+
+/// A request the server has let go of, to be finished by another thread.
+struct AsyncRequest(*mut httpd_req_t);
 
-        let mut buf = vec![0; len];
-        req.read_exact(&mut buf)?;
-
-        if let Ok(config) = serde_json::from_slice::<Config>(&buf) {
-            info!("Config: {:?}", config);
-
-            match config.validate() {
-                Ok(_) => {
-                    let internal_config = InternalConfig::from(config);
-                    match s.save(&internal_config) {
-                        Ok(_) => {
-                            sender.send(internal_config).unwrap();
-                            req.into_response(
-                                200,
-                                Some("OK"),
-                                &[("Content-Type", "application/json")],
-                            )?
-                            .write_all("{\"status\":\"ok\"}".as_bytes())?;
-                        }
-                        Err(_) => {
-                            req.into_status_response(500)?;
-                        }
-                    };
-                }
-                Err(e) => {
-                    let j = serde_json::to_string(&e).unwrap();
-                    req.into_status_response(400)?.write_all(j.as_bytes())?;
-                    return Ok(());
-                }
+// SAFETY: async requests are made to be finished from other threads
+unsafe impl Send for AsyncRequest {}
+
+/// Sends events from a thread of its own, since the server handles one
+/// request at a time.
+fn stream_events(
+    mut req: EspRequest<&mut EspHttpConnection>,
+    response: Response,
+    events: Subscription,
+) -> Result<()> {
+    let raw: *mut httpd_req_t = req.connection().raw_connection()?.handle();
+    let mut detached: *mut httpd_req_t = std::ptr::null_mut();
+    // SAFETY: `detached` stays valid until it is completed below
+    esp!(unsafe { sys::httpd_req_async_handler_begin(raw, &mut detached) })?;
+
+    let detached = AsyncRequest(detached);
+    std::thread::Builder::new()
+        .stack_size(EVENT_STREAM_STACK_SIZE)
+        .spawn(move || {
+            let req = detached;
+            if let Err(e) = send_events(req.0, &response, &events) {
+                info!("Event stream closed: {:?}", e);
             }
+            // SAFETY: nothing uses the request after this
+            unsafe { sys::httpd_req_async_handler_complete(req.0) };
+        })?;
+    Ok(())
+}
+
+/// Sends events until the browser goes away or falls behind.
+fn send_events(
+    req: *mut httpd_req_t,
+    response: &Response,
+    events: &Subscription,
+) -> Result<(), EspError> {
+    // The server keeps pointers to these until it sends the first chunk
+    let mut content_type = None;
+    let mut headers = Vec::new();
+    for (name, value) in response.headers() {
+        let value = CString::new(value).unwrap();
+        if name.eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(value);
         } else {
-            req.into_status_response(400)?;
+            headers.push((CString::new(name).unwrap(), value));
         }
+    }
 
-        Ok(())
-    })?;
-    Ok(server)
+    // SAFETY: the strings outlive every chunk sent below
+    unsafe {
+        esp!(sys::httpd_resp_set_status(req, c"200 OK".as_ptr()))?;
+        if let Some(content_type) = &content_type {
+            esp!(sys::httpd_resp_set_type(req, content_type.as_ptr()))?;
+        }
+        for (name, value) in &headers {
+            esp!(sys::httpd_resp_set_hdr(req, name.as_ptr(), value.as_ptr()))?;
+        }
+    }
+
+    loop {
+        let chunk = match events.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => String::from(KEEP_ALIVE),
+            // Dropped for falling behind, the browser will reconnect
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        esp!(unsafe { sys::httpd_resp_send_chunk(req, chunk.as_ptr().cast(), chunk.len() as _) })?;
+    }
+    esp!(unsafe { sys::httpd_resp_send_chunk(req, std::ptr::null(), 0) })
+}
+
+fn esp_method(method: Method) -> EspMethod {
+    match method {
+        Method::Get => EspMethod::Get,
+        Method::Post => EspMethod::Post,
+        Method::Put => EspMethod::Put,
+        Method::Patch => EspMethod::Patch,
+        Method::Delete => EspMethod::Delete,
+    }
+}
+
+/// Restarts once the response has gone out.
+fn restart_soon() {
+    std::thread::spawn(|| {
+        std::thread::sleep(Duration::from_secs(1));
+        restart();
+    });
 }
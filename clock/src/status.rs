@@ -0,0 +1,71 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use drivers::status::{FirmwareInfo, ResetReason, SyncState, SystemStatus};
+use esp_idf_svc::sntp::{EspSntp, SntpConf, SyncStatus};
+use esp_idf_svc::sys::{self, EspError};
+
+use crate::secrets::secrets_protected;
+
+/// Set by `build.rs` from `git rev-parse`.
+const GIT_HASH: &str = env!("GIT_HASH");
+
+/// When SNTP last set the clock.
+pub type LastSync = Arc<Mutex<Option<DateTime<Utc>>>>;
+
+/// Starts SNTP, recording each time it sets the clock in `last_sync`.
+pub fn start_sntp(last_sync: LastSync) -> Result<EspSntp<'static>, EspError> {
+    EspSntp::new_with_callback(&SntpConf::default(), move |synced: Duration| {
+        let synced = DateTime::from_timestamp(synced.as_secs() as i64, synced.subsec_nanos());
+        *last_sync.lock().unwrap() = synced;
+    })
+}
+
+pub fn sync_state(status: SyncStatus) -> SyncState {
+    match status {
+        SyncStatus::Reset => SyncState::NotSynced,
+        SyncStatus::InProgress => SyncState::InProgress,
+        SyncStatus::Completed => SyncState::Synced,
+    }
+}
+
+pub fn system_status() -> SystemStatus {
+    // SAFETY: these only read counters kept by ESP-IDF
+    let (uptime_us, free_heap, min_free_heap, reason) = unsafe {
+        (
+            sys::esp_timer_get_time(),
+            sys::esp_get_free_heap_size(),
+            sys::esp_get_minimum_free_heap_size(),
+            sys::esp_reset_reason(),
+        )
+    };
+    SystemStatus {
+        uptime_secs: uptime_us as u64 / 1_000_000,
+        free_heap,
+        min_free_heap,
+        reset_reason: reset_reason(reason),
+        secrets_protected: secrets_protected(),
+    }
+}
+
+pub fn firmware() -> FirmwareInfo {
+    FirmwareInfo::new(env!("CARGO_PKG_VERSION"), GIT_HASH)
+}
+
+#[allow(non_upper_case_globals)]
+fn reset_reason(reason: sys::esp_reset_reason_t) -> ResetReason {
+    match reason {
+        sys::esp_reset_reason_t_ESP_RST_POWERON => ResetReason::PowerOn,
+        sys::esp_reset_reason_t_ESP_RST_EXT => ResetReason::External,
+        sys::esp_reset_reason_t_ESP_RST_SW => ResetReason::Software,
+        sys::esp_reset_reason_t_ESP_RST_PANIC => ResetReason::Panic,
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT => ResetReason::InterruptWatchdog,
+        sys::esp_reset_reason_t_ESP_RST_TASK_WDT => ResetReason::TaskWatchdog,
+        sys::esp_reset_reason_t_ESP_RST_WDT => ResetReason::Watchdog,
+        sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => ResetReason::DeepSleep,
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => ResetReason::Brownout,
+        sys::esp_reset_reason_t_ESP_RST_SDIO => ResetReason::Sdio,
+        _ => ResetReason::Unknown,
+    }
+}
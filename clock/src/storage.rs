@@ -1,15 +1,29 @@
+use std::ffi::CString;
+
 use drivers::storage::{Storage, StorageError};
 use esp_idf_svc::nvs::*;
-use esp_idf_svc::sys::EspError;
+use esp_idf_svc::sys::{self, esp, EspError};
 
 pub struct NvsStorage<T: NvsPartitionId> {
     nvs: EspNvs<T>,
+    partition: CString,
+    namespace: CString,
 }
 
 impl<T: NvsPartitionId> NvsStorage<T> {
-    pub fn new(partition: EspNvsPartition<T>, namespace: &str) -> Result<Self, EspError> {
+    /// Opens `namespace` on the partition named `partition_name`, which must
+    /// be the one `partition` was taken with.
+    pub fn new(
+        partition: EspNvsPartition<T>,
+        partition_name: &str,
+        namespace: &str,
+    ) -> Result<Self, EspError> {
         let nvs = EspNvs::new(partition, namespace, true)?;
-        Ok(NvsStorage { nvs })
+        Ok(NvsStorage {
+            nvs,
+            partition: CString::new(partition_name).unwrap(),
+            namespace: CString::new(namespace).unwrap(),
+        })
     }
 }
 
@@ -17,12 +31,53 @@ impl<T: NvsPartitionId> Storage for NvsStorage<T> {
     fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool, StorageError> {
         self.nvs
             .set_raw(name, buf)
-            .map_err(|_| StorageError::WriteError)
+            .map_err(|e| StorageError::WriteError(Box::new(e)))
     }
 
-    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, StorageError> {
+    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<&'a [u8], StorageError> {
+        // NVS refuses to read a blob into a short buffer without saying how
+        // much room it needs, so check the stored length first.
+        let len = self
+            .nvs
+            .blob_len(name)
+            .map_err(|e| StorageError::ReadError(Box::new(e)))?
+            .ok_or_else(|| StorageError::NotFound(name.to_string()))?;
+
+        if buf.len() < len {
+            return Err(StorageError::BufferTooSmall { required: len });
+        }
+
         self.nvs
             .get_raw(name, buf)
-            .map_err(|_| StorageError::ReadError)
+            .map_err(|e| StorageError::ReadError(Box::new(e)))?
+            .ok_or_else(|| StorageError::NotFound(name.to_string()))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool, StorageError> {
+        self.nvs
+            .remove(name)
+            .map_err(|e| StorageError::WriteError(Box::new(e)))
+    }
+
+    fn erase_all(&mut self) -> Result<(), StorageError> {
+        // `EspNvs` has no way to erase a namespace, so open a second handle
+        // to it for that
+        let mut handle: sys::nvs_handle_t = 0;
+        // SAFETY: the handle is only used between opening and closing it
+        let result = unsafe {
+            esp!(sys::nvs_open_from_partition(
+                self.partition.as_ptr(),
+                self.namespace.as_ptr(),
+                sys::nvs_open_mode_t_NVS_READWRITE,
+                &mut handle,
+            ))
+            .and_then(|()| {
+                let erased =
+                    esp!(sys::nvs_erase_all(handle)).and_then(|()| esp!(sys::nvs_commit(handle)));
+                sys::nvs_close(handle);
+                erased
+            })
+        };
+        result.map_err(|e| StorageError::WriteError(Box::new(e)))
     }
 }
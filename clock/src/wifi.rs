@@ -1,44 +1,284 @@
+use std::net::Ipv4Addr;
+use std::sync::mpsc::{channel, Receiver};
+
 use anyhow::Result;
 
-use drivers::config::{DefaultConfig, InternalConfig};
+use drivers::config::{DefaultConfig, InternalConfig, StaticIp};
+use drivers::wifi::{KnownNetwork, LinkEvent, ScanResult, WifiAuth, WifiDriver};
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::ipv4::{self, ClientSettings, Mask, RouterConfiguration, Subnet};
+use esp_idf_svc::netif::{EspNetif, IpEvent, NetifConfiguration, NetifStack};
+use esp_idf_svc::sys::{self, EspError};
 use esp_idf_svc::wifi::{
-    AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi,
+    config::ScanConfig, AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration,
+    Configuration, EspWifi, WifiDeviceId, WifiEvent,
 };
 
 use log::info;
 
-pub fn configure_wifi(
-    wifi: &mut BlockingWifi<&mut EspWifi>,
-    app_config: &InternalConfig,
-    default_config: &DefaultConfig,
-) -> Result<()> {
-    info!("Configuring wifi with SSID: {}", app_config.wifi_ssid());
-    info!(
-        "Configuring access point with SSID: {} Pass: {}",
-        default_config.ap_ssid, default_config.ap_pass
-    );
-    wifi.set_configuration(&Configuration::Mixed(
-        ClientConfiguration {
-            ssid: app_config.wifi_ssid().try_into().unwrap(),
-            password: app_config.wifi_pass().try_into().unwrap(),
-            auth_method: AuthMethod::None,
-            ..Default::default()
-        },
-        AccessPointConfiguration {
-            ssid: default_config.ap_ssid.try_into().unwrap(),
-            password: default_config.ap_pass.try_into().unwrap(),
+/// Drives an `EspWifi` for the connection manager, running the setup access
+/// point alongside the station while it is enabled. Whether it starts out
+/// enabled is given up front, so it isn't broadcast even briefly when it
+/// shouldn't be.
+pub struct EspWifiDriver<'a, 'd> {
+    wifi: BlockingWifi<&'a mut EspWifi<'d>>,
+    client: ClientConfiguration,
+    ap: AccessPointConfiguration,
+    ap_enabled: bool,
+    scanning: bool,
+    events: Receiver<LinkEvent>,
+    // Keep them around or else the events stop
+    _subscriptions: [EspSubscription<'static, System>; 2],
+}
+
+impl<'a, 'd> EspWifiDriver<'a, 'd> {
+    pub fn new(
+        wifi: BlockingWifi<&'a mut EspWifi<'d>>,
+        sys_loop: &EspSystemEventLoop,
+        default_config: &DefaultConfig,
+        ap_enabled: bool,
+    ) -> Result<Self, EspError> {
+        let (up, events) = channel();
+        let down = up.clone();
+        let subscriptions = [
+            sys_loop.subscribe::<IpEvent, _>(move |event| {
+                if matches!(event, IpEvent::DhcpIpAssigned(..)) {
+                    let _ = up.send(LinkEvent::Up);
+                }
+            })?,
+            sys_loop.subscribe::<WifiEvent, _>(move |event| {
+                if matches!(event, WifiEvent::StaDisconnected(..)) {
+                    let _ = down.send(LinkEvent::Down);
+                }
+            })?,
+        ];
+
+        Ok(EspWifiDriver {
+            wifi,
+            client: ClientConfiguration::default(),
+            ap: AccessPointConfiguration {
+                ssid: default_config.ap_ssid.try_into().unwrap(),
+                ..Default::default()
+            },
+            ap_enabled,
+            scanning: false,
+            events,
+            _subscriptions: subscriptions,
+        })
+    }
+
+    /// Restarts WiFi with the network interface and access point settings of
+    /// `app_config`. Joining a network is left to the connection manager.
+    pub fn configure(&mut self, app_config: &InternalConfig) -> Result<()> {
+        if self.wifi.is_started()? {
+            self.wifi.stop()?;
+        }
+        set_static_ip(&mut self.wifi, app_config.static_ip())?;
+        set_ap_dns(&mut self.wifi)?;
+        self.set_hostname(&app_config.hostname_or_default(&self.mac()?))?;
+
+        info!("Configuring access point with SSID: {}", self.ap.ssid);
+        self.ap.password = app_config.ap_pass().try_into().unwrap();
+        self.ap.auth_method = if app_config.ap_pass().is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::WPA2Personal
+        };
+        self.client = ClientConfiguration::default();
+        self.apply()?;
+        self.wifi.start()?;
+        info!("Wifi started");
+
+        Ok(())
+    }
+
+    pub fn set_access_point(&mut self, enabled: bool) -> Result<()> {
+        if enabled != self.ap_enabled {
+            info!(
+                "{} access point",
+                if enabled { "Starting" } else { "Stopping" }
+            );
+            self.ap_enabled = enabled;
+            self.apply()?;
+        }
+        Ok(())
+    }
+
+    pub fn mac(&self) -> Result<[u8; 6], EspError> {
+        self.wifi.wifi().get_mac(WifiDeviceId::Sta)
+    }
+
+    /// Sets the hostname sent to DHCP servers. A change reaches the DHCP
+    /// server when the lease is next renewed.
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<(), EspError> {
+        self.wifi.wifi_mut().sta_netif_mut().set_hostname(hostname)
+    }
+
+    /// Signal strength of the access point the clock is connected to.
+    pub fn rssi(&self) -> Option<i8> {
+        let mut info = sys::wifi_ap_record_t::default();
+        // SAFETY: `info` is only written to
+        sys::esp!(unsafe { sys::esp_wifi_sta_get_ap_info(&mut info) }).ok()?;
+        Some(info.rssi)
+    }
+
+    /// Address the clock got on the network it is connected to.
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        let ip = self.wifi.wifi().sta_netif().get_ip_info().ok()?.ip;
+        (!ip.is_unspecified()).then_some(ip)
+    }
+
+    /// Address of the clock on its own access point.
+    pub fn ap_address(&self) -> Ipv4Addr {
+        RouterConfiguration::default().subnet.gateway
+    }
+
+    /// Length of the network prefix of the access point.
+    pub fn ap_prefix_len(&self) -> u8 {
+        RouterConfiguration::default().subnet.mask.0
+    }
+
+    fn apply(&mut self) -> Result<(), EspError> {
+        let configuration = if self.ap_enabled {
+            Configuration::Mixed(self.client.clone(), self.ap.clone())
+        } else {
+            Configuration::Client(self.client.clone())
+        };
+        self.wifi.set_configuration(&configuration)
+    }
+}
+
+impl WifiDriver for EspWifiDriver<'_, '_> {
+    type Error = EspError;
+
+    fn start_scan(&mut self) -> Result<(), EspError> {
+        self.wifi
+            .wifi_mut()
+            .start_scan(&ScanConfig::default(), false)?;
+        self.scanning = true;
+        Ok(())
+    }
+
+    fn scan_results(&mut self) -> Option<Result<Vec<ScanResult>, EspError>> {
+        if !self.scanning {
+            return None;
+        }
+        match self.wifi.wifi().is_scan_done() {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(e) => {
+                self.scanning = false;
+                return Some(Err(e));
+            }
+        }
+
+        self.scanning = false;
+        Some(self.wifi.wifi_mut().get_scan_result().map(|aps| {
+            aps.into_iter()
+                .map(|ap| ScanResult {
+                    ssid: ap.ssid.to_string(),
+                    rssi: ap.signal_strength,
+                    channel: ap.channel,
+                    auth: ap.auth_method.map(wifi_auth).unwrap_or_default(),
+                })
+                .collect()
+        }))
+    }
+
+    fn connect(&mut self, network: &KnownNetwork) -> Result<(), EspError> {
+        info!(
+            "Configuring wifi with SSID: {} Auth: {:?}",
+            network.ssid, network.auth
+        );
+        self.client = ClientConfiguration {
+            ssid: network.ssid.as_str().try_into().unwrap(),
+            password: network.pass.as_str().try_into().unwrap(),
+            auth_method: auth_method(network.auth),
             ..Default::default()
-        },
-    ))?;
+        };
+        self.apply()?;
+
+        // Don't wait for the connection, the manager polls for it
+        self.wifi.wifi_mut().connect()
+    }
+
+    fn disconnect(&mut self) -> Result<(), EspError> {
+        self.wifi.wifi_mut().disconnect()
+    }
 
-    wifi.start()?;
-    info!("Wifi started");
+    fn next_event(&mut self) -> Option<LinkEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+/// Replaces the station interface with one using fixed settings, or with a
+/// DHCP client when there are none. Only takes effect while WiFi is stopped.
+fn set_static_ip(wifi: &mut BlockingWifi<&mut EspWifi>, static_ip: Option<StaticIp>) -> Result<()> {
+    let netif = match static_ip {
+        Some(static_ip) => {
+            info!(
+                "Using static IP: {}/{} Gateway: {} DNS: {}",
+                static_ip.address(),
+                static_ip.prefix_len(),
+                static_ip.gateway(),
+                static_ip.dns()
+            );
+            EspNetif::new_with_conf(&NetifConfiguration {
+                ip_configuration: Some(ipv4::Configuration::Client(
+                    ipv4::ClientConfiguration::Fixed(ClientSettings {
+                        ip: static_ip.address(),
+                        subnet: Subnet {
+                            gateway: static_ip.gateway(),
+                            mask: Mask(static_ip.prefix_len()),
+                        },
+                        dns: Some(static_ip.dns()),
+                        secondary_dns: None,
+                    }),
+                )),
+                ..NetifConfiguration::wifi_default_client()
+            })?
+        }
+        None => EspNetif::new(NetifStack::Sta)?,
+    };
 
-    wifi.connect()?;
-    info!("Wifi connected");
+    wifi.wifi_mut().swap_netif_sta(netif)?;
+    Ok(())
+}
 
-    wifi.wait_netif_up()?;
-    info!("Wifi netif up");
+/// Replaces the access point interface with one that hands out the clock
+/// itself as DNS server, so the captive portal can answer lookups. Only takes
+/// effect while WiFi is stopped.
+fn set_ap_dns(wifi: &mut BlockingWifi<&mut EspWifi>) -> Result<()> {
+    let router = RouterConfiguration::default();
+    let netif = EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: Some(ipv4::Configuration::Router(RouterConfiguration {
+            dns: Some(router.subnet.gateway),
+            secondary_dns: None,
+            ..router
+        })),
+        ..NetifConfiguration::wifi_default_router()
+    })?;
 
+    wifi.wifi_mut().swap_netif_ap(netif)?;
     Ok(())
 }
+
+fn auth_method(auth: WifiAuth) -> AuthMethod {
+    match auth {
+        // The driver detects the method itself when given none
+        WifiAuth::Auto | WifiAuth::Open => AuthMethod::None,
+        WifiAuth::Wpa2Personal => AuthMethod::WPA2Personal,
+        WifiAuth::Wpa2Wpa3Personal => AuthMethod::WPA2WPA3Personal,
+        WifiAuth::Wpa3Personal => AuthMethod::WPA3Personal,
+    }
+}
+
+fn wifi_auth(method: AuthMethod) -> WifiAuth {
+    match method {
+        AuthMethod::None => WifiAuth::Open,
+        AuthMethod::WPA2Personal => WifiAuth::Wpa2Personal,
+        AuthMethod::WPA2WPA3Personal => WifiAuth::Wpa2Wpa3Personal,
+        AuthMethod::WPA3Personal => WifiAuth::Wpa3Personal,
+        _ => WifiAuth::Auto,
+    }
+}
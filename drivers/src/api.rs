@@ -0,0 +1,419 @@
+//! Web Server Endpoints
+//!
+//! The clock's HTTP API as handlers on a [`Router`]. Handlers only see the
+//! [`Request`] and the [`AppState`] they share, so the whole API runs and is
+//! tested on the host. The device registers the routes on its HTTP server,
+//! and supplies what only it can do, like restarting.
+
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::auth::{Authenticator, Lockout, SessionStore};
+use crate::config::{ConfigStorage, InternalConfig};
+use crate::events::{ClockEvent, EventBus, Subscription};
+use crate::http::{ApiError, ErrorCode, Method, Request, Response, Router};
+use crate::nixie_display::OverrideStack;
+use crate::status::DeviceStatus;
+use crate::wifi::{ScanResult, WifiStatus};
+
+mod auth;
+mod config;
+mod display;
+mod networks;
+mod status;
+mod wifi;
+
+/// Browsers that can be signed in at once.
+const MAX_SESSIONS: usize = 4;
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Wrong passwords allowed in a row before signing in is locked.
+const MAX_FAILED_SIGN_INS: u32 = 5;
+const INITIAL_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// How long to wait for the main loop to scan for networks.
+const SCAN_TIMEOUT: Duration = Duration::from_secs(15);
+/// Pages phones and browsers fetch to find out whether they are behind a
+/// captive portal. Redirecting them while the setup access point is up makes
+/// the config page pop up.
+const CAPTIVE_PORTAL_PROBES: [&str; 8] = [
+    "/generate_204",
+    "/gen_204",
+    "/hotspot-detect.html",
+    "/library/test/success.html",
+    "/connecttest.txt",
+    "/ncsi.txt",
+    "/canonical.html",
+    "/success.txt",
+];
+
+/// Asks the main loop, which owns the WiFi driver, for a scan. The nearby
+/// networks are sent back, or the sender is dropped if the scan failed.
+pub type ScanRequest = Sender<Vec<ScanResult>>;
+
+/// Everything the handlers share. Saved configs are also sent to the main
+/// loop, which applies them, keeps the statuses up to date and publishes
+/// events.
+pub struct AppState {
+    config_storage: Arc<Mutex<ConfigStorage>>,
+    config_changes: Sender<InternalConfig>,
+    wifi_status: Arc<Mutex<WifiStatus>>,
+    device_status: Arc<Mutex<DeviceStatus>>,
+    events: Arc<EventBus>,
+    overrides: Arc<Mutex<OverrideStack>>,
+    scan_requests: Sender<ScanRequest>,
+    auth: Mutex<Authenticator>,
+    index_html: &'static str,
+    portal_url: String,
+    /// Whether the setup access point is up. Captive portal checks are only
+    /// answered then, and are not found otherwise.
+    captive_portal: Arc<AtomicBool>,
+    restart: Box<dyn Fn() + Send + Sync>,
+    scan_timeout: Duration,
+}
+
+impl AppState {
+    pub fn new(
+        config_storage: Arc<Mutex<ConfigStorage>>,
+        config_changes: Sender<InternalConfig>,
+        wifi_status: Arc<Mutex<WifiStatus>>,
+        device_status: Arc<Mutex<DeviceStatus>>,
+        events: Arc<EventBus>,
+        overrides: Arc<Mutex<OverrideStack>>,
+        scan_requests: Sender<ScanRequest>,
+    ) -> Self {
+        AppState {
+            config_storage,
+            config_changes,
+            wifi_status,
+            device_status,
+            events,
+            overrides,
+            scan_requests,
+            auth: Mutex::new(Authenticator::new(
+                SessionStore::new(MAX_SESSIONS, SESSION_IDLE_TIMEOUT),
+                Lockout::new(MAX_FAILED_SIGN_INS, INITIAL_LOCKOUT, MAX_LOCKOUT),
+            )),
+            index_html: "",
+            portal_url: String::from("http://192.168.71.1/"),
+            captive_portal: Arc::new(AtomicBool::new(false)),
+            restart: Box::new(|| {}),
+            scan_timeout: SCAN_TIMEOUT,
+        }
+    }
+
+    /// The config page, served at `/`.
+    pub fn with_index_html(mut self, index_html: &'static str) -> Self {
+        self.index_html = index_html;
+        self
+    }
+
+    /// The address of the setup access point, where captive portal checks
+    /// are sent.
+    pub fn with_ap_address(mut self, address: Ipv4Addr) -> Self {
+        self.portal_url = format!("http://{}/", address);
+        self
+    }
+
+    /// Set while the setup access point is up, by the main loop.
+    pub fn with_captive_portal(mut self, enabled: Arc<AtomicBool>) -> Self {
+        self.captive_portal = enabled;
+        self
+    }
+
+    /// Called after a factory reset, before the response is sent. It should
+    /// wait a moment so the response gets out.
+    pub fn with_restart(mut self, restart: impl Fn() + Send + Sync + 'static) -> Self {
+        self.restart = Box::new(restart);
+        self
+    }
+
+    pub fn with_scan_timeout(mut self, scan_timeout: Duration) -> Self {
+        self.scan_timeout = scan_timeout;
+        self
+    }
+
+    fn load(&self) -> Result<InternalConfig, ApiError> {
+        Ok(self.config_storage.lock().unwrap().load()?)
+    }
+
+    /// Saves a changed config, hands it to the main loop and lets event
+    /// subscribers know.
+    fn save(&self, storage: &mut ConfigStorage, config: InternalConfig) -> Result<(), ApiError> {
+        storage.save(&config)?;
+        self.config_changes
+            .send(config)
+            .map_err(|_| ApiError::new(ErrorCode::Unavailable, "the clock is shutting down"))?;
+        self.events.publish(ClockEvent::ConfigChanged);
+        Ok(())
+    }
+
+    /// Checks that a request carries the admin password or a session cookie.
+    fn authorize(&self, req: &Request) -> Result<(), ApiError> {
+        // A config that can't be read must not look like one without a
+        // password, which would let anyone set one
+        let admin_pass = self.load()?.admin_pass().cloned();
+
+        self.auth
+            .lock()
+            .unwrap()
+            .authorize(
+                admin_pass.as_ref(),
+                req.header("Authorization"),
+                req.header("Cookie"),
+                Instant::now(),
+            )
+            .map_err(|e| {
+                info!("Refused request to {}: {}", req.path(), e);
+                e.into()
+            })
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    let router = Router::new()
+        .route(Method::Get, "/", index)
+        .route(Method::Get, "/config", config::get)
+        .route(Method::Post, "/config", config::replace)
+        .route(Method::Patch, "/config", config::patch)
+        .route(Method::Get, "/config/source", config::source)
+        .route(Method::Get, "/config/export", config::export)
+        .route(Method::Post, "/config/import", config::import)
+        .route(Method::Post, "/factory-reset", config::factory_reset)
+        .route(Method::Get, "/networks", networks::list)
+        .route(Method::Post, "/networks", networks::save)
+        .route(Method::Delete, "/networks", networks::remove)
+        .route(Method::Get, "/status", status::get)
+        .route(Method::Post, "/display", display::show)
+        .route(Method::Get, "/wifi/status", wifi::status)
+        .route(Method::Get, "/wifi/scan", wifi::scan)
+        .route(Method::Get, "/auth", auth::status)
+        .route(Method::Post, "/auth/password", auth::set_password)
+        .route(Method::Post, "/auth/sign-in", auth::sign_in)
+        .route(Method::Post, "/auth/sign-out", auth::sign_out);
+
+    CAPTIVE_PORTAL_PROBES.iter().fold(router, |router, probe| {
+        router.route(Method::Get, probe, captive_portal)
+    })
+}
+
+/// Starts a `GET /events` stream. Streams outlive a request, so the device
+/// sends the response headers and then the subscription's events itself.
+pub fn subscribe_events(state: &AppState) -> Result<(Response, Subscription), ApiError> {
+    let subscription = state.events.subscribe().ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::Unavailable,
+            "too many browsers are following the clock",
+        )
+    })?;
+    let response = Response::new(200)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache");
+    Ok((response, subscription))
+}
+
+fn index(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    Ok(Response::html(state.index_html))
+}
+
+fn captive_portal(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    if !state.captive_portal.load(Ordering::Relaxed) {
+        return Err(ApiError::new(ErrorCode::NotFound, "not found"));
+    }
+    Ok(Response::redirect(&state.portal_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Receiver};
+
+    use super::*;
+    use crate::auth::{PasswordHash, SESSION_COOKIE};
+    use crate::events::MAX_SUBSCRIBERS;
+    use crate::secrets::{ChaChaCipher, StaticKey};
+    use crate::storage::InMemoryStorage;
+
+    pub const PASSWORD: &str = "correct horse";
+
+    /// The API with its state, and the ends of the channels the main loop
+    /// would hold.
+    pub struct Fixture {
+        pub state: AppState,
+        pub changes: Receiver<InternalConfig>,
+        pub scans: Receiver<ScanRequest>,
+        router: Router<AppState>,
+    }
+
+    impl Fixture {
+        pub fn new(config: InternalConfig) -> Self {
+            let cipher = ChaChaCipher::new(&StaticKey::new([7; 32])).unwrap();
+            let mut storage =
+                ConfigStorage::new(Box::new(InMemoryStorage::new()), Box::new(cipher));
+            storage.save(&config).unwrap();
+
+            let (changes_tx, changes) = channel();
+            let (scans_tx, scans) = channel();
+            let state = AppState::new(
+                Arc::new(Mutex::new(storage)),
+                changes_tx,
+                Arc::new(Mutex::new(WifiStatus::default())),
+                Arc::new(Mutex::new(DeviceStatus::default())),
+                Arc::new(EventBus::default()),
+                Arc::new(Mutex::new(OverrideStack::default())),
+                scans_tx,
+            );
+            Fixture {
+                state,
+                changes,
+                scans,
+                router: router(),
+            }
+        }
+
+        /// A clock with the admin password set to `PASSWORD`.
+        pub fn with_password() -> Self {
+            let admin_pass = PasswordHash::new(PASSWORD, 10).unwrap();
+            Fixture::new(config().with_admin_pass(Some(admin_pass)))
+        }
+
+        pub fn send(&self, req: Request) -> Response {
+            self.router.handle(&req, &self.state)
+        }
+
+        /// Signs in and returns the `Cookie` header to send.
+        pub fn sign_in(&self) -> String {
+            let body = format!(r#"{{"password":"{}"}}"#, PASSWORD);
+            let response =
+                self.send(Request::new(Method::Post, "/auth/sign-in").with_body(body.as_bytes()));
+            let cookie = response.header("Set-Cookie").unwrap();
+            let token = cookie.split(';').next().unwrap();
+            assert!(token.starts_with(SESSION_COOKIE));
+            String::from(token)
+        }
+
+        pub fn stored(&self) -> InternalConfig {
+            self.state.load().unwrap()
+        }
+    }
+
+    pub fn config() -> InternalConfig {
+        InternalConfig::new("home", "hunter22", "US/Central", 0x123456, false)
+    }
+
+    pub fn body(response: &Response) -> serde_json::Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[test]
+    fn it_serves_the_config_page() {
+        let mut fixture = Fixture::new(config());
+        fixture.state = fixture.state.with_index_html("<h1>Nixie Clock</h1>");
+
+        let response = fixture.send(Request::new(Method::Get, "/"));
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Type"), Some("text/html"));
+        assert_eq!(response.body(), b"<h1>Nixie Clock</h1>");
+    }
+
+    #[test]
+    fn it_redirects_captive_portal_checks() {
+        let mut fixture = Fixture::new(config());
+        fixture.state = fixture
+            .state
+            .with_ap_address(Ipv4Addr::new(10, 0, 0, 1))
+            .with_captive_portal(Arc::new(AtomicBool::new(true)));
+
+        for probe in CAPTIVE_PORTAL_PROBES {
+            let response = fixture.send(Request::new(Method::Get, probe));
+
+            assert_eq!(response.status(), 302);
+            assert_eq!(response.header("Location"), Some("http://10.0.0.1/"));
+        }
+    }
+
+    #[test]
+    fn it_ignores_captive_portal_checks_without_the_access_point() {
+        let enabled = Arc::new(AtomicBool::new(true));
+        let mut fixture = Fixture::new(config());
+        fixture.state = fixture.state.with_captive_portal(enabled.clone());
+
+        enabled.store(false, Ordering::Relaxed);
+
+        for probe in CAPTIVE_PORTAL_PROBES {
+            let response = fixture.send(Request::new(Method::Get, probe));
+
+            assert_eq!(response.status(), 404);
+        }
+    }
+
+    #[test]
+    fn it_refuses_changes_without_the_password() {
+        let fixture = Fixture::with_password();
+        let changes = [
+            (Method::Post, "/config"),
+            (Method::Patch, "/config"),
+            (Method::Get, "/config/export"),
+            (Method::Post, "/config/import"),
+            (Method::Post, "/factory-reset"),
+            (Method::Post, "/display"),
+            (Method::Post, "/networks"),
+            (Method::Delete, "/networks"),
+        ];
+
+        for (method, path) in changes {
+            let response = fixture.send(Request::new(method, path).with_body(b"{}"));
+            assert_eq!(response.status(), 401, "{:?} {}", method, path);
+        }
+        assert!(fixture.changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn it_streams_events() {
+        let fixture = Fixture::new(config());
+
+        let (response, events) = subscribe_events(&fixture.state).unwrap();
+        fixture
+            .state
+            .save(&mut fixture.state.config_storage.lock().unwrap(), config())
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Type"), Some("text/event-stream"));
+        assert_eq!(
+            events.recv_timeout(Duration::ZERO),
+            Ok(ClockEvent::ConfigChanged.to_sse())
+        );
+    }
+
+    #[test]
+    fn it_limits_event_streams() {
+        let fixture = Fixture::new(config());
+        let _streams: Vec<_> = (0..MAX_SUBSCRIBERS)
+            .map(|_| subscribe_events(&fixture.state).unwrap())
+            .collect();
+
+        let response = Response::from(subscribe_events(&fixture.state).err().unwrap());
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(body(&response)["code"], "unavailable");
+    }
+
+    #[test]
+    fn it_answers_unknown_paths() {
+        let fixture = Fixture::new(config());
+
+        assert_eq!(
+            fixture.send(Request::new(Method::Get, "/nope")).status(),
+            404
+        );
+        assert_eq!(
+            fixture.send(Request::new(Method::Put, "/config")).status(),
+            405
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Instant;
+
+use log::info;
+use serde::Deserialize;
+
+use super::AppState;
+use crate::auth::{
+    expired_session_cookie, session_cookie, validate_admin_password, PasswordHash, DEFAULT_ROUNDS,
+};
+use crate::http::{ApiError, ErrorCode, Request, Response};
+
+#[derive(Deserialize)]
+struct PasswordForm {
+    password: String,
+}
+
+pub(super) fn status(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    let password_set = state.load()?.admin_pass().is_some();
+    let signed_in = state.authorize(req).is_ok();
+    Response::json(&serde_json::json!({ "passwordSet": password_set, "signedIn": signed_in }))
+}
+
+/// Sets the admin password and signs in with it.
+pub(super) fn set_password(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    // The first password can be set by anyone, changing it takes the current
+    // one
+    let current = state.load()?;
+    if current.admin_pass().is_some() {
+        state.authorize(req)?;
+    }
+
+    let form: PasswordForm = req.json()?;
+    if validate_admin_password(&form.password).is_err() {
+        return Err(ApiError::new(ErrorCode::Invalid, "some fields are invalid")
+            .with_field("password", "password must be 8 to 64 characters"));
+    }
+
+    let config = current.with_admin_pass(Some(PasswordHash::new(&form.password, DEFAULT_ROUNDS)?));
+    state.save(&mut state.config_storage.lock().unwrap(), config)?;
+    info!("Admin password changed");
+
+    let token = state
+        .auth
+        .lock()
+        .unwrap()
+        .password_changed(Instant::now())?;
+    Ok(Response::ok().with_header("Set-Cookie", &session_cookie(&token)))
+}
+
+pub(super) fn sign_in(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    let form: PasswordForm = req.json()?;
+    let config = state.load()?;
+
+    let token = state
+        .auth
+        .lock()
+        .unwrap()
+        .sign_in(config.admin_pass(), &form.password, Instant::now())
+        .map_err(|e| {
+            info!("Refused sign in: {}", e);
+            ApiError::from(e)
+        })?;
+    Ok(Response::ok().with_header("Set-Cookie", &session_cookie(&token)))
+}
+
+pub(super) fn sign_out(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.auth.lock().unwrap().sign_out(req.header("Cookie"));
+    Ok(Response::ok().with_header("Set-Cookie", &expired_session_cookie()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{body, config, Fixture, PASSWORD};
+    use super::*;
+    use crate::http::Method;
+
+    fn set_password(fixture: &Fixture, password: &str, cookie: Option<&str>) -> Response {
+        let body = format!(r#"{{"password":"{}"}}"#, password);
+        let mut req = Request::new(Method::Post, "/auth/password").with_body(body.as_bytes());
+        if let Some(cookie) = cookie {
+            req = req.with_header("Cookie", cookie);
+        }
+        fixture.send(req)
+    }
+
+    #[test]
+    fn it_reports_whether_a_password_is_set() {
+        let fixture = Fixture::new(config());
+
+        let response = fixture.send(Request::new(Method::Get, "/auth"));
+
+        assert_eq!(
+            body(&response),
+            serde_json::json!({ "passwordSet": false, "signedIn": false })
+        );
+    }
+
+    #[test]
+    fn it_lets_anyone_set_the_first_password() {
+        let fixture = Fixture::new(config());
+
+        let response = set_password(&fixture, PASSWORD, None);
+
+        assert_eq!(response.status(), 200);
+        let cookie = response
+            .header("Set-Cookie")
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap();
+        let status = fixture.send(Request::new(Method::Get, "/auth").with_header("Cookie", cookie));
+        assert_eq!(
+            body(&status),
+            serde_json::json!({ "passwordSet": true, "signedIn": true })
+        );
+        assert!(fixture.stored().admin_pass().unwrap().verify(PASSWORD));
+        assert!(fixture.changes.try_recv().is_ok());
+    }
+
+    #[test]
+    fn it_takes_the_password_to_change_it() {
+        let fixture = Fixture::with_password();
+
+        assert_eq!(set_password(&fixture, "new password", None).status(), 401);
+
+        let cookie = fixture.sign_in();
+        let response = set_password(&fixture, "new password", Some(&cookie));
+        assert_eq!(response.status(), 200);
+        assert!(fixture
+            .stored()
+            .admin_pass()
+            .unwrap()
+            .verify("new password"));
+    }
+
+    #[test]
+    fn it_rejects_short_passwords() {
+        let fixture = Fixture::new(config());
+
+        let response = set_password(&fixture, "short", None);
+
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            body(&response)["fields"]["password"][0],
+            "password must be 8 to 64 characters"
+        );
+        assert_eq!(fixture.stored().admin_pass(), None);
+    }
+
+    #[test]
+    fn it_signs_in_and_out() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+        let signed_in = || {
+            let response =
+                fixture.send(Request::new(Method::Get, "/auth").with_header("Cookie", &cookie));
+            body(&response)["signedIn"].as_bool().unwrap()
+        };
+        assert!(signed_in());
+
+        let response = fixture
+            .send(Request::new(Method::Post, "/auth/sign-out").with_header("Cookie", &cookie));
+
+        assert!(response.header("Set-Cookie").unwrap().contains("Max-Age=0"));
+        assert!(!signed_in());
+    }
+
+    #[test]
+    fn it_refuses_the_wrong_password() {
+        let fixture = Fixture::with_password();
+
+        let response = fixture.send(
+            Request::new(Method::Post, "/auth/sign-in").with_body(br#"{"password":"wrong"}"#),
+        );
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(body(&response)["code"], "unauthorized");
+        assert!(response.header("Set-Cookie").is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use log::info;
+
+use super::AppState;
+use crate::config::{ConfigExport, ConfigPatch, ConfigUpdate, ConfigView, InternalConfig};
+use crate::http::{ApiError, ErrorCode, Request, Response};
+
+pub(super) fn get(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    Response::json(&ConfigView::from(state.load()?))
+}
+
+/// Replaces the settings on the form with the ones sent.
+pub(super) fn replace(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+    let update: ConfigUpdate = req.json()?;
+
+    let mut storage = state.config_storage.lock().unwrap();
+    let current = storage.load()?;
+    let config = update.merge(&current);
+    config.validate()?;
+
+    // Saved networks are managed through /networks and the access point
+    // password never leaves the clock
+    let config = InternalConfig::from(config).with_device_fields_from(&current);
+    state.save(&mut storage, config)?;
+    Ok(Response::ok())
+}
+
+/// Changes only the settings sent.
+pub(super) fn patch(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+
+    let mut storage = state.config_storage.lock().unwrap();
+    let current = storage.load()?;
+    let config = ConfigPatch::apply(req.body(), &current)?;
+    state.save(&mut storage, config)?;
+    Ok(Response::ok())
+}
+
+pub(super) fn source(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    let source = state.config_storage.lock().unwrap().source();
+    Response::json(&serde_json::json!({ "source": source }))
+}
+
+pub(super) fn export(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+    let include_secrets = req.query("secrets") != Some("false");
+    let json = ConfigExport::new(&state.load()?, include_secrets)
+        .to_json()
+        .map_err(|e| ApiError::new(ErrorCode::Internal, &e.to_string()))?;
+
+    Ok(Response::new(200)
+        .with_header("Content-Type", "application/json")
+        .with_header(
+            "Content-Disposition",
+            "attachment; filename=\"nixie-clock-config.json\"",
+        )
+        .with_body(json.as_bytes()))
+}
+
+pub(super) fn import(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+
+    let mut storage = state.config_storage.lock().unwrap();
+    let current = storage.load()?;
+    let config = ConfigExport::import(req.body(), &current).map_err(|e| {
+        info!("Rejected config import: {}", e);
+        ApiError::from(e)
+    })?;
+    state.save(&mut storage, config)?;
+    Ok(Response::ok())
+}
+
+pub(super) fn factory_reset(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+    state.config_storage.lock().unwrap().factory_reset()?;
+
+    info!("Factory reset, restarting");
+    (state.restart)();
+    Ok(Response::ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::super::tests::{body, config, Fixture};
+    use crate::config::PASSWORD_PLACEHOLDER;
+    use crate::http::Method;
+    use crate::wifi::KnownNetwork;
+
+    use super::*;
+
+    #[test]
+    fn it_hides_the_wifi_password() {
+        let fixture = Fixture::new(config());
+
+        let response = fixture.send(Request::new(Method::Get, "/config"));
+
+        assert_eq!(response.status(), 200);
+        let json = body(&response);
+        assert_eq!(json["wifiSsid"], "home");
+        assert_eq!(json["hasPassword"], true);
+        assert!(json.get("wifiPass").is_none());
+    }
+
+    #[test]
+    fn it_replaces_the_config() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+        let update = format!(
+            r##"{{"wifiSsid":"office","wifiPass":"{}","timeZone":"US/Eastern","ledColor":"#ff0000","hours_24":true}}"##,
+            PASSWORD_PLACEHOLDER
+        );
+
+        let response = fixture.send(
+            Request::new(Method::Post, "/config")
+                .with_header("Cookie", &cookie)
+                .with_body(update.as_bytes()),
+        );
+
+        assert_eq!(response.status(), 200);
+        let stored = fixture.stored();
+        assert_eq!(stored.wifi_ssid(), "office");
+        assert_eq!(stored.wifi_pass(), "hunter22");
+        assert!(stored.admin_pass().is_some());
+        assert_eq!(fixture.changes.try_recv().unwrap(), stored);
+    }
+
+    #[test]
+    fn it_reports_invalid_fields() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+
+        let response = fixture.send(
+            Request::new(Method::Patch, "/config")
+                .with_header("Cookie", &cookie)
+                .with_body(br#"{"ledColor":"red"}"#),
+        );
+
+        assert_eq!(response.status(), 400);
+        let json = body(&response);
+        assert_eq!(json["code"], "invalid");
+        assert_eq!(json["fields"]["ledColor"][0], "led color is invalid");
+        assert_eq!(fixture.stored().led_color(), 0x123456);
+        assert!(fixture.changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn it_patches_the_config() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+
+        let response = fixture.send(
+            Request::new(Method::Patch, "/config")
+                .with_header("Cookie", &cookie)
+                .with_body(br#"{"hours_24":true}"#),
+        );
+
+        assert_eq!(response.status(), 200);
+        let stored = fixture.stored();
+        assert!(stored.hours_24());
+        assert_eq!(stored.wifi_ssid(), "home");
+        assert!(fixture.changes.try_recv().is_ok());
+    }
+
+    #[test]
+    fn it_reports_where_the_config_came_from() {
+        let fixture = Fixture::new(config());
+
+        let response = fixture.send(Request::new(Method::Get, "/config/source"));
+
+        assert_eq!(body(&response)["source"], "current");
+    }
+
+    #[test]
+    fn it_exports_and_imports_backups() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+        let backup = fixture
+            .send(Request::new(Method::Get, "/config/export").with_header("Cookie", &cookie));
+        assert_eq!(backup.status(), 200);
+        assert!(backup
+            .header("Content-Disposition")
+            .unwrap()
+            .starts_with("attachment"));
+
+        let mut changed = fixture.stored();
+        changed.save_network(KnownNetwork::new("office", "office-pass", 1));
+        fixture
+            .state
+            .config_storage
+            .lock()
+            .unwrap()
+            .save(&changed)
+            .unwrap();
+
+        let response = fixture.send(
+            Request::new(Method::Post, "/config/import")
+                .with_header("Cookie", &cookie)
+                .with_body(backup.body()),
+        );
+
+        assert_eq!(response.status(), 200);
+        assert!(fixture.stored().networks().is_empty());
+    }
+
+    #[test]
+    fn it_leaves_secrets_out_of_the_export_on_request() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+
+        let response = fixture.send(
+            Request::new(Method::Get, "/config/export?secrets=false")
+                .with_header("Cookie", &cookie),
+        );
+
+        assert_eq!(body(&response)["config"]["wifiPass"], "");
+    }
+
+    #[test]
+    fn it_rejects_broken_backups() {
+        let fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+
+        let response = fixture.send(
+            Request::new(Method::Post, "/config/import")
+                .with_header("Cookie", &cookie)
+                .with_body(br#"{"format":"something-else"}"#),
+        );
+
+        assert_eq!(response.status(), 400);
+        assert_eq!(body(&response)["code"], "bad_request");
+    }
+
+    #[test]
+    fn it_only_factory_resets_with_the_password() {
+        let restarted = Arc::new(AtomicBool::new(false));
+        let flag = restarted.clone();
+        let reset = |fixture: &Fixture, authorization: &str| {
+            fixture.send(
+                Request::new(Method::Post, "/factory-reset")
+                    .with_header("Authorization", authorization),
+            )
+        };
+
+        // Nobody can wipe a clock that has no password yet
+        let mut unprotected = Fixture::new(config());
+        unprotected.state = unprotected
+            .state
+            .with_restart(move || flag.store(true, Ordering::Relaxed));
+        assert_eq!(reset(&unprotected, "Basic YWRtaW46").status(), 403);
+        assert_eq!(unprotected.stored(), config());
+
+        let protected = Fixture::with_password();
+        // admin:wrong
+        assert_eq!(reset(&protected, "Basic YWRtaW46d3Jvbmc=").status(), 401);
+        assert!(protected.stored().admin_pass().is_some());
+        assert!(!restarted.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn it_restarts_after_a_factory_reset() {
+        let restarted = Arc::new(AtomicBool::new(false));
+        let mut fixture = Fixture::with_password();
+        let cookie = fixture.sign_in();
+        let flag = restarted.clone();
+        fixture.state = fixture
+            .state
+            .with_restart(move || flag.store(true, Ordering::Relaxed));
+
+        let response = fixture
+            .send(Request::new(Method::Post, "/factory-reset").with_header("Cookie", &cookie));
+
+        assert_eq!(response.status(), 200);
+        assert!(restarted.load(Ordering::Relaxed));
+        assert_eq!(fixture.stored().admin_pass(), None);
+    }
+}
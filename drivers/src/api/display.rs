@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use validator::{Validate, ValidationError};
+
+use super::AppState;
+use crate::config::validate_color;
+use crate::http::{ApiError, ErrorCode, Request, Response};
+use crate::nixie_display::DisplayOverride;
+
+/// Longest an override can stay on the tubes.
+const MAX_OVERRIDE_SECS: u64 = 60 * 60;
+
+#[derive(Deserialize, Validate)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct DisplayForm {
+    /// `null` for a dark tube. Left out, all four are dark.
+    #[serde(default)]
+    #[validate(custom(
+        function = "validate_digits",
+        message = "digits must be 0 to 9, or null for a blank tube"
+    ))]
+    digits: [Option<u8>; 4],
+    #[serde(default)]
+    separators: [bool; 2],
+    #[validate(
+        length(equal = 7, message = "led color is invalid"),
+        custom(function = "validate_color", message = "led color is invalid")
+    )]
+    led_color: Option<String>,
+    #[validate(range(
+        min = 1,
+        max = MAX_OVERRIDE_SECS,
+        message = "duration must be 1 second to 1 hour"
+    ))]
+    duration_secs: u64,
+    #[serde(default)]
+    priority: u8,
+}
+
+fn validate_digits(digits: &[Option<u8>; 4]) -> Result<(), ValidationError> {
+    if digits.iter().flatten().any(|&digit| digit > 9) {
+        return Err(ValidationError::new("invalid_digit"));
+    }
+    Ok(())
+}
+
+impl From<DisplayForm> for DisplayOverride {
+    fn from(form: DisplayForm) -> Self {
+        DisplayOverride {
+            digits: form.digits,
+            separators: form.separators,
+            led_color: form
+                .led_color
+                .and_then(|color| u32::from_str_radix(&color[1..], 16).ok()),
+            priority: form.priority,
+        }
+    }
+}
+
+/// Shows something on the tubes for a while, in place of the time.
+pub(super) fn show(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+    let form: DisplayForm = req.json()?;
+    form.validate()?;
+
+    let duration = Duration::from_secs(form.duration_secs);
+    let mut overrides = state.overrides.lock().unwrap();
+    if !overrides.push(form.into(), duration, Instant::now()) {
+        return Err(ApiError::new(
+            ErrorCode::Unavailable,
+            "the tubes are showing more important things",
+        ));
+    }
+    Ok(Response::ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{body, Fixture};
+    use super::*;
+    use crate::http::Method;
+    use crate::nixie_display::MAX_OVERRIDES;
+
+    fn show(fixture: &Fixture, body: &str) -> Response {
+        let cookie = fixture.sign_in();
+        fixture.send(
+            Request::new(Method::Post, "/display")
+                .with_header("Cookie", &cookie)
+                .with_body(body.as_bytes()),
+        )
+    }
+
+    fn current(fixture: &Fixture) -> Option<DisplayOverride> {
+        let mut overrides = fixture.state.overrides.lock().unwrap();
+        overrides.current(Instant::now()).cloned()
+    }
+
+    #[test]
+    fn it_shows_overrides() {
+        let fixture = Fixture::with_password();
+
+        let response = show(
+            &fixture,
+            r##"{"digits":[null,4,2,null],"separators":[true,false],"ledColor":"#ff8000","durationSecs":30}"##,
+        );
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            current(&fixture),
+            Some(DisplayOverride {
+                digits: [None, Some(4), Some(2), None],
+                separators: [true, false],
+                led_color: Some(0xff8000),
+                priority: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn it_blanks_the_tubes_without_digits() {
+        let fixture = Fixture::with_password();
+
+        show(&fixture, r#"{"durationSecs":5}"#);
+
+        assert_eq!(current(&fixture).unwrap().digits, [None; 4]);
+    }
+
+    #[test]
+    fn it_reports_invalid_overrides() {
+        let fixture = Fixture::with_password();
+
+        let response = show(
+            &fixture,
+            r#"{"digits":[1,2,3,10],"ledColor":"red","durationSecs":0}"#,
+        );
+
+        assert_eq!(response.status(), 400);
+        let fields = &body(&response)["fields"];
+        assert_eq!(
+            fields["digits"][0],
+            "digits must be 0 to 9, or null for a blank tube"
+        );
+        assert_eq!(fields["ledColor"][0], "led color is invalid");
+        assert_eq!(
+            fields["durationSecs"][0],
+            "duration must be 1 second to 1 hour"
+        );
+        assert_eq!(current(&fixture), None);
+    }
+
+    #[test]
+    fn it_refuses_overrides_when_outranked() {
+        let fixture = Fixture::with_password();
+        for _ in 0..MAX_OVERRIDES {
+            show(&fixture, r#"{"durationSecs":60,"priority":5}"#);
+        }
+
+        let response = show(&fixture, r#"{"durationSecs":60,"priority":1}"#);
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(current(&fixture).unwrap().priority, 5);
+    }
+
+    #[test]
+    fn it_takes_the_password() {
+        let fixture = Fixture::with_password();
+
+        let response = fixture
+            .send(Request::new(Method::Post, "/display").with_body(br#"{"durationSecs":5}"#));
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(current(&fixture), None);
+    }
+}
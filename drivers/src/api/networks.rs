@@ -0,0 +1,134 @@
+use serde::Deserialize;
+
+use super::AppState;
+use crate::http::{ApiError, ErrorCode, Request, Response};
+use crate::wifi::KnownNetwork;
+
+#[derive(Deserialize)]
+struct NetworkForm {
+    ssid: String,
+}
+
+pub(super) fn list(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    let config = state.load()?;
+    // Passwords never leave the clock
+    let networks: Vec<_> = config
+        .networks()
+        .iter()
+        .map(|n| serde_json::json!({ "ssid": n.ssid, "auth": n.auth, "priority": n.priority }))
+        .collect();
+    Response::json(&networks)
+}
+
+/// Adds a network, or updates the one with the same SSID.
+pub(super) fn save(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+    let network: KnownNetwork = req.json()?;
+    network.validate()?;
+
+    let mut storage = state.config_storage.lock().unwrap();
+    let mut config = storage.load()?;
+    if !config.save_network(network) {
+        return Err(ApiError::new(ErrorCode::Invalid, "too many saved networks"));
+    }
+    state.save(&mut storage, config)?;
+    Ok(Response::ok())
+}
+
+pub(super) fn remove(req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    state.authorize(req)?;
+    let form: NetworkForm = req.json()?;
+
+    let mut storage = state.config_storage.lock().unwrap();
+    let mut config = storage.load()?;
+    if !config.remove_network(&form.ssid) {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            &format!("no saved network named {}", form.ssid),
+        ));
+    }
+    state.save(&mut storage, config)?;
+    Ok(Response::ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{body, config, Fixture, PASSWORD};
+    use super::*;
+    use crate::auth::PasswordHash;
+    use crate::http::Method;
+
+    fn fixture_with_network() -> Fixture {
+        let admin_pass = PasswordHash::new(PASSWORD, 10).unwrap();
+        let mut config = config().with_admin_pass(Some(admin_pass));
+        config.save_network(KnownNetwork::new("office", "office-pass", 2));
+        Fixture::new(config)
+    }
+
+    #[test]
+    fn it_lists_networks_without_passwords() {
+        let fixture = fixture_with_network();
+
+        let response = fixture.send(Request::new(Method::Get, "/networks"));
+
+        assert_eq!(
+            body(&response),
+            serde_json::json!([{ "ssid": "office", "auth": "auto", "priority": 2 }])
+        );
+    }
+
+    #[test]
+    fn it_saves_networks() {
+        let fixture = fixture_with_network();
+        let cookie = fixture.sign_in();
+
+        let response = fixture.send(
+            Request::new(Method::Post, "/networks")
+                .with_header("Cookie", &cookie)
+                .with_body(br#"{"ssid":"cabin","password":"cabin-pass","priority":1}"#),
+        );
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(fixture.stored().networks().len(), 2);
+        assert!(fixture.changes.try_recv().is_ok());
+    }
+
+    #[test]
+    fn it_reports_invalid_networks() {
+        let fixture = fixture_with_network();
+        let cookie = fixture.sign_in();
+
+        let response = fixture.send(
+            Request::new(Method::Post, "/networks")
+                .with_header("Cookie", &cookie)
+                .with_body(br#"{"ssid":"","password":"","priority":1}"#),
+        );
+
+        assert_eq!(response.status(), 400);
+        assert_eq!(
+            body(&response)["fields"]["ssid"][0],
+            "SSID must not be blank"
+        );
+    }
+
+    #[test]
+    fn it_removes_networks() {
+        let fixture = fixture_with_network();
+        let cookie = fixture.sign_in();
+        let remove = |ssid: &str| {
+            let body = format!(r#"{{"ssid":"{}"}}"#, ssid);
+            fixture.send(
+                Request::new(Method::Delete, "/networks")
+                    .with_header("Cookie", &cookie)
+                    .with_body(body.as_bytes()),
+            )
+        };
+
+        assert_eq!(remove("office").status(), 200);
+        assert!(fixture.stored().networks().is_empty());
+
+        let response = remove("office");
+        assert_eq!(response.status(), 404);
+        assert_eq!(body(&response)["code"], "not_found");
+    }
+}
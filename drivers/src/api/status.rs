@@ -0,0 +1,29 @@
+use super::AppState;
+use crate::http::{ApiError, Request, Response};
+
+pub(super) fn get(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    Response::json(&*state.device_status.lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{body, config, Fixture};
+    use super::*;
+    use crate::http::Method;
+    use crate::status::{FirmwareInfo, STATUS_SCHEMA_VERSION};
+
+    #[test]
+    fn it_reports_the_device_status() {
+        let fixture = Fixture::new(config());
+        fixture.state.device_status.lock().unwrap().firmware =
+            FirmwareInfo::new("0.2.0", "abc1234");
+
+        let response = fixture.send(Request::new(Method::Get, "/status"));
+
+        assert_eq!(response.status(), 200);
+        let status = body(&response);
+        assert_eq!(status["schemaVersion"], STATUS_SCHEMA_VERSION);
+        assert_eq!(status["firmware"]["version"], "0.2.0");
+        assert_eq!(status["firmware"]["gitHash"], "abc1234");
+    }
+}
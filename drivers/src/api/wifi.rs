@@ -0,0 +1,76 @@
+use std::sync::mpsc::channel;
+
+use super::AppState;
+use crate::http::{ApiError, ErrorCode, Request, Response};
+
+pub(super) fn status(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    Response::json(&*state.wifi_status.lock().unwrap())
+}
+
+/// Asks the main loop for a scan and waits for the nearby networks.
+pub(super) fn scan(_req: &Request, state: &AppState) -> Result<Response, ApiError> {
+    let (reply, results) = channel();
+    let scan_failed = || ApiError::new(ErrorCode::Unavailable, "scan failed");
+
+    state.scan_requests.send(reply).map_err(|_| scan_failed())?;
+    let networks = results
+        .recv_timeout(state.scan_timeout)
+        .map_err(|_| scan_failed())?;
+    Response::json(&networks)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::tests::{body, config, Fixture};
+    use super::*;
+    use crate::http::Method;
+    use crate::wifi::{ScanResult, WifiAuth};
+
+    #[test]
+    fn it_reports_the_wifi_status() {
+        let fixture = Fixture::new(config());
+
+        let response = fixture.send(Request::new(Method::Get, "/wifi/status"));
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(body(&response)["lastChange"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn it_scans_through_the_main_loop() {
+        let mut fixture = Fixture::new(config());
+        let scans = mem::replace(&mut fixture.scans, channel().1);
+        let main_loop = thread::spawn(move || {
+            let reply = scans.recv().unwrap();
+            reply
+                .send(vec![ScanResult {
+                    ssid: String::from("home"),
+                    rssi: -40,
+                    channel: 6,
+                    auth: WifiAuth::Wpa2Personal,
+                }])
+                .unwrap();
+        });
+
+        let response = fixture.send(Request::new(Method::Get, "/wifi/scan"));
+        main_loop.join().unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(body(&response)[0]["ssid"], "home");
+    }
+
+    #[test]
+    fn it_reports_failed_scans() {
+        let mut fixture = Fixture::new(config());
+        fixture.state = fixture.state.with_scan_timeout(Duration::from_millis(10));
+
+        let response = fixture.send(Request::new(Method::Get, "/wifi/scan"));
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(body(&response)["code"], "unavailable");
+    }
+}
@@ -0,0 +1,324 @@
+//! Web Server Authentication
+//!
+//! Changing the clock's settings through the web server takes the admin
+//! password, which the owner sets the first time they use the config page.
+//! A request proves it knows the password either with HTTP Basic auth or
+//! with the cookie of a session started by signing in. Repeated wrong
+//! passwords lock everyone out for a while.
+
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use thiserror::Error;
+
+use crate::secrets::SecretError;
+
+mod lockout;
+mod password;
+mod session;
+
+pub use lockout::Lockout;
+pub use password::{
+    validate_admin_password, PasswordHash, DEFAULT_ROUNDS, MAX_ADMIN_PASSWORD_LEN,
+    MIN_ADMIN_PASSWORD_LEN,
+};
+pub use session::SessionStore;
+
+/// Name of the cookie holding the session token.
+pub const SESSION_COOKIE: &str = "session";
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("no admin password is set")]
+    NoPassword,
+    #[error("wrong password or expired session")]
+    Unauthorized,
+    #[error("too many failed attempts, try again in {} seconds", .0.as_secs())]
+    LockedOut(Duration),
+    #[error("error starting session")]
+    Session(#[from] SecretError),
+}
+
+pub struct Authenticator {
+    sessions: SessionStore,
+    lockout: Lockout,
+}
+
+impl Authenticator {
+    pub fn new(sessions: SessionStore, lockout: Lockout) -> Self {
+        Authenticator { sessions, lockout }
+    }
+
+    /// Checks the `Authorization` and `Cookie` headers of a request against
+    /// the admin password.
+    pub fn authorize(
+        &mut self,
+        admin_pass: Option<&PasswordHash>,
+        authorization: Option<&str>,
+        cookie: Option<&str>,
+        now: Instant,
+    ) -> Result<(), AuthError> {
+        let admin_pass = admin_pass.ok_or(AuthError::NoPassword)?;
+
+        if let Some(token) = cookie.and_then(session_token) {
+            if self.sessions.validate(token, now) {
+                return Ok(());
+            }
+        }
+        match authorization.and_then(basic_password) {
+            Some(password) => self.check_password(admin_pass, &password, now),
+            None => Err(AuthError::Unauthorized),
+        }
+    }
+
+    /// Checks the password and starts a session, returning its token.
+    pub fn sign_in(
+        &mut self,
+        admin_pass: Option<&PasswordHash>,
+        password: &str,
+        now: Instant,
+    ) -> Result<String, AuthError> {
+        let admin_pass = admin_pass.ok_or(AuthError::NoPassword)?;
+        self.check_password(admin_pass, password, now)?;
+        Ok(self.sessions.create(now)?)
+    }
+
+    pub fn sign_out(&mut self, cookie: Option<&str>) {
+        if let Some(token) = cookie.and_then(session_token) {
+            self.sessions.revoke(token);
+        }
+    }
+
+    /// Ends every session after the password changes, and starts a new one
+    /// for whoever changed it.
+    pub fn password_changed(&mut self, now: Instant) -> Result<String, AuthError> {
+        self.sessions.clear();
+        self.lockout.record_success();
+        Ok(self.sessions.create(now)?)
+    }
+
+    fn check_password(
+        &mut self,
+        admin_pass: &PasswordHash,
+        password: &str,
+        now: Instant,
+    ) -> Result<(), AuthError> {
+        if let Some(remaining) = self.lockout.remaining(now) {
+            return Err(AuthError::LockedOut(remaining));
+        }
+        if admin_pass.verify(password) {
+            self.lockout.record_success();
+            Ok(())
+        } else {
+            self.lockout.record_failure(now);
+            Err(AuthError::Unauthorized)
+        }
+    }
+}
+
+/// The `Set-Cookie` value that hands a session token to the browser.
+pub fn session_cookie(token: &str) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict",
+        SESSION_COOKIE, token
+    )
+}
+
+/// The `Set-Cookie` value that removes the session cookie.
+pub fn expired_session_cookie() -> String {
+    format!(
+        "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0",
+        SESSION_COOKIE
+    )
+}
+
+/// Finds the session token in a `Cookie` header.
+fn session_token(cookie: &str) -> Option<&str> {
+    cookie
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token)
+}
+
+/// Takes the password out of a Basic `Authorization` header. Any user name
+/// is accepted since there is only the one admin.
+fn basic_password(authorization: &str) -> Option<String> {
+    let (scheme, encoded) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    decoded
+        .split_once(':')
+        .map(|(_, password)| password.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "correct horse";
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(
+            SessionStore::new(4, Duration::from_secs(600)),
+            Lockout::new(3, Duration::from_secs(30), Duration::from_secs(300)),
+        )
+    }
+
+    fn admin_pass() -> PasswordHash {
+        PasswordHash::new(PASSWORD, 10).unwrap()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn it_needs_a_password_to_be_set() {
+        let mut auth = authenticator();
+        let now = Instant::now();
+
+        assert!(matches!(
+            auth.authorize(None, Some(&basic("admin:anything")), None, now),
+            Err(AuthError::NoPassword)
+        ));
+    }
+
+    #[test]
+    fn it_accepts_basic_auth_with_the_password() {
+        let mut auth = authenticator();
+        let admin_pass = admin_pass();
+        let now = Instant::now();
+
+        let header = basic(&format!("admin:{}", PASSWORD));
+        assert!(auth
+            .authorize(Some(&admin_pass), Some(&header), None, now)
+            .is_ok());
+
+        let header = basic("admin:wrong");
+        assert!(matches!(
+            auth.authorize(Some(&admin_pass), Some(&header), None, now),
+            Err(AuthError::Unauthorized)
+        ));
+        assert!(matches!(
+            auth.authorize(Some(&admin_pass), None, None, now),
+            Err(AuthError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn it_accepts_the_cookie_of_a_session() {
+        let mut auth = authenticator();
+        let admin_pass = admin_pass();
+        let now = Instant::now();
+
+        let token = auth.sign_in(Some(&admin_pass), PASSWORD, now).unwrap();
+        let cookie = format!("theme=dark; {}={}", SESSION_COOKIE, token);
+
+        assert!(auth
+            .authorize(Some(&admin_pass), None, Some(&cookie), now)
+            .is_ok());
+
+        auth.sign_out(Some(&cookie));
+        assert!(auth
+            .authorize(Some(&admin_pass), None, Some(&cookie), now)
+            .is_err());
+    }
+
+    #[test]
+    fn it_refuses_to_sign_in_with_the_wrong_password() {
+        let mut auth = authenticator();
+        let now = Instant::now();
+
+        assert!(matches!(
+            auth.sign_in(Some(&admin_pass()), "wrong", now),
+            Err(AuthError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn it_locks_out_after_repeated_failures() {
+        let mut auth = authenticator();
+        let admin_pass = admin_pass();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(auth.sign_in(Some(&admin_pass), "wrong", now).is_err());
+        }
+
+        // Even the right password is refused until the lockout ends
+        assert!(matches!(
+            auth.sign_in(Some(&admin_pass), PASSWORD, now),
+            Err(AuthError::LockedOut(_))
+        ));
+        let header = basic(&format!("admin:{}", PASSWORD));
+        assert!(matches!(
+            auth.authorize(Some(&admin_pass), Some(&header), None, now),
+            Err(AuthError::LockedOut(_))
+        ));
+
+        let later = now + Duration::from_secs(30);
+        assert!(auth.sign_in(Some(&admin_pass), PASSWORD, later).is_ok());
+    }
+
+    #[test]
+    fn it_keeps_sessions_working_during_a_lockout() {
+        let mut auth = authenticator();
+        let admin_pass = admin_pass();
+        let now = Instant::now();
+        let token = auth.sign_in(Some(&admin_pass), PASSWORD, now).unwrap();
+
+        for _ in 0..3 {
+            assert!(auth.sign_in(Some(&admin_pass), "wrong", now).is_err());
+        }
+
+        let cookie = format!("{}={}", SESSION_COOKIE, token);
+        assert!(auth
+            .authorize(Some(&admin_pass), None, Some(&cookie), now)
+            .is_ok());
+    }
+
+    #[test]
+    fn it_ends_sessions_when_the_password_changes() {
+        let mut auth = authenticator();
+        let admin_pass = admin_pass();
+        let now = Instant::now();
+        let old = auth.sign_in(Some(&admin_pass), PASSWORD, now).unwrap();
+
+        let new = auth.password_changed(now).unwrap();
+
+        let cookie = format!("{}={}", SESSION_COOKIE, old);
+        assert!(auth
+            .authorize(Some(&admin_pass), None, Some(&cookie), now)
+            .is_err());
+        let cookie = format!("{}={}", SESSION_COOKIE, new);
+        assert!(auth
+            .authorize(Some(&admin_pass), None, Some(&cookie), now)
+            .is_ok());
+    }
+
+    #[test]
+    fn it_parses_basic_auth_headers() {
+        assert_eq!(
+            basic_password(&basic("admin:pass:word")),
+            Some(String::from("pass:word"))
+        );
+        assert_eq!(
+            basic_password(&format!("basic {}", STANDARD.encode(":pass"))),
+            Some(String::from("pass"))
+        );
+        assert_eq!(basic_password("Bearer abc"), None);
+        assert_eq!(basic_password("Basic !!!"), None);
+        assert_eq!(basic_password(&basic("no-colon")), None);
+    }
+
+    #[test]
+    fn it_finds_the_session_cookie() {
+        assert_eq!(session_token("session=abc"), Some("abc"));
+        assert_eq!(session_token("a=1; session=abc; b=2"), Some("abc"));
+        assert_eq!(session_token("sessions=abc"), None);
+        assert_eq!(session_token(""), None);
+    }
+}
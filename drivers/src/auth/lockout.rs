@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+/// Slows down password guessing. After a number of wrong passwords in a row,
+/// no password is checked until the lockout ends, and each further failure
+/// doubles the lockout up to a maximum.
+pub struct Lockout {
+    max_failures: u32,
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Lockout {
+    pub fn new(max_failures: u32, initial: Duration, max: Duration) -> Self {
+        Lockout {
+            max_failures,
+            initial,
+            max,
+            failures: 0,
+            locked_until: None,
+        }
+    }
+
+    /// Returns how long until passwords are checked again, or `None` if they
+    /// are checked now.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .map(|until| until.saturating_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.max_failures {
+            let doublings = (self.failures - self.max_failures).min(16);
+            let duration = self.initial.saturating_mul(1 << doublings).min(self.max);
+            self.locked_until = Some(now + duration);
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_secs(30);
+    const MAX: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn it_allows_a_few_mistakes() {
+        let now = Instant::now();
+        let mut lockout = Lockout::new(3, INITIAL, MAX);
+
+        lockout.record_failure(now);
+        lockout.record_failure(now);
+
+        assert_eq!(lockout.remaining(now), None);
+    }
+
+    #[test]
+    fn it_locks_out_after_repeated_failures() {
+        let now = Instant::now();
+        let mut lockout = Lockout::new(3, INITIAL, MAX);
+
+        for _ in 0..3 {
+            lockout.record_failure(now);
+        }
+
+        assert_eq!(lockout.remaining(now), Some(INITIAL));
+        assert_eq!(
+            lockout.remaining(now + Duration::from_secs(10)),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(lockout.remaining(now + INITIAL), None);
+    }
+
+    #[test]
+    fn it_doubles_the_lockout_up_to_the_maximum() {
+        let now = Instant::now();
+        let mut lockout = Lockout::new(1, INITIAL, MAX);
+
+        lockout.record_failure(now);
+        assert_eq!(lockout.remaining(now), Some(INITIAL));
+        lockout.record_failure(now);
+        assert_eq!(lockout.remaining(now), Some(INITIAL * 2));
+        for _ in 0..40 {
+            lockout.record_failure(now);
+        }
+        assert_eq!(lockout.remaining(now), Some(MAX));
+    }
+
+    #[test]
+    fn it_resets_after_a_success() {
+        let now = Instant::now();
+        let mut lockout = Lockout::new(3, INITIAL, MAX);
+        for _ in 0..3 {
+            lockout.record_failure(now);
+        }
+
+        lockout.record_success();
+        lockout.record_failure(now);
+
+        assert_eq!(lockout.remaining(now), None);
+    }
+}
@@ -0,0 +1,95 @@
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use validator::ValidationError;
+
+use crate::secrets::SecretError;
+
+/// PBKDF2 rounds for new passwords. Checking a password takes a fraction of
+/// a second on the clock, which is slow enough to make guessing expensive.
+pub const DEFAULT_ROUNDS: u32 = 10_000;
+pub const MIN_ADMIN_PASSWORD_LEN: usize = 8;
+pub const MAX_ADMIN_PASSWORD_LEN: usize = 64;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// The admin password as it is stored: a salted PBKDF2-HMAC-SHA256 hash.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PasswordHash {
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+    rounds: u32,
+}
+
+impl PasswordHash {
+    pub fn new(password: &str, rounds: u32) -> Result<Self, SecretError> {
+        let mut salt = [0; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|_| SecretError::Random)?;
+
+        Ok(PasswordHash {
+            salt,
+            hash: derive(password, &salt, rounds),
+            rounds,
+        })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        derive(password, &self.salt, self.rounds)
+            .ct_eq(&self.hash)
+            .into()
+    }
+}
+
+fn derive(password: &str, salt: &[u8], rounds: u32) -> [u8; HASH_LEN] {
+    let mut hash = [0; HASH_LEN];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut hash);
+    hash
+}
+
+pub fn validate_admin_password(password: &str) -> Result<(), ValidationError> {
+    if !(MIN_ADMIN_PASSWORD_LEN..=MAX_ADMIN_PASSWORD_LEN).contains(&password.chars().count()) {
+        return Err(ValidationError::new("invalid_length"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_verifies_the_password() {
+        let hash = PasswordHash::new("correct horse", 10).unwrap();
+
+        assert!(hash.verify("correct horse"));
+        assert!(!hash.verify("correct horse "));
+        assert!(!hash.verify(""));
+    }
+
+    #[test]
+    fn it_salts_each_hash() {
+        let first = PasswordHash::new("correct horse", 10).unwrap();
+        let second = PasswordHash::new("correct horse", 10).unwrap();
+
+        assert_ne!(first, second);
+        assert!(second.verify("correct horse"));
+    }
+
+    #[test]
+    fn it_matches_the_pbkdf2_test_vector() {
+        // RFC 7914 section 11
+        let hash = derive("passwd", b"salt", 1);
+
+        assert_eq!(hash[..8], [0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f]);
+    }
+
+    #[test]
+    fn it_validates_the_password_length() {
+        assert!(validate_admin_password("1234567").is_err());
+        assert!(validate_admin_password("12345678").is_ok());
+        assert!(validate_admin_password(&"p".repeat(64)).is_ok());
+        assert!(validate_admin_password(&"p".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+use subtle::ConstantTimeEq;
+
+use crate::secrets::SecretError;
+
+const TOKEN_BYTES: usize = 16;
+
+struct Session {
+    token: String,
+    expires: Instant,
+}
+
+/// Signed-in browsers, each known by a random token kept in a cookie. A
+/// session lasts until it goes unused for the idle timeout. When the store
+/// is full, signing in again pushes out the session closest to expiring.
+pub struct SessionStore {
+    sessions: Vec<Session>,
+    capacity: usize,
+    idle_timeout: Duration,
+}
+
+impl SessionStore {
+    pub fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        SessionStore {
+            sessions: Vec::with_capacity(capacity),
+            capacity,
+            idle_timeout,
+        }
+    }
+
+    /// Starts a session and returns its token.
+    pub fn create(&mut self, now: Instant) -> Result<String, SecretError> {
+        let mut random = [0; TOKEN_BYTES];
+        getrandom::getrandom(&mut random).map_err(|_| SecretError::Random)?;
+        let token: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+
+        self.sessions.retain(|s| s.expires > now);
+        if self.sessions.len() >= self.capacity {
+            if let Some(oldest) = (0..self.sessions.len()).min_by_key(|&i| self.sessions[i].expires)
+            {
+                self.sessions.swap_remove(oldest);
+            }
+        }
+        self.sessions.push(Session {
+            token: token.clone(),
+            expires: now + self.idle_timeout,
+        });
+
+        Ok(token)
+    }
+
+    /// Checks a token, extending its session if it is still live.
+    pub fn validate(&mut self, token: &str, now: Instant) -> bool {
+        let idle_timeout = self.idle_timeout;
+        match self.find(token) {
+            Some(session) if session.expires > now => {
+                session.expires = now + idle_timeout;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn revoke(&mut self, token: &str) {
+        self.sessions.retain(|s| !matches(&s.token, token));
+    }
+
+    /// Ends every session, such as when the password changes.
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+
+    fn find(&mut self, token: &str) -> Option<&mut Session> {
+        self.sessions.iter_mut().find(|s| matches(&s.token, token))
+    }
+}
+
+/// Compares tokens in constant time, so timing doesn't give them away.
+fn matches(expected: &str, token: &str) -> bool {
+    expected.as_bytes().ct_eq(token.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE: Duration = Duration::from_secs(600);
+
+    #[test]
+    fn it_accepts_the_tokens_it_created() {
+        let now = Instant::now();
+        let mut sessions = SessionStore::new(2, IDLE);
+
+        let token = sessions.create(now).unwrap();
+
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert!(sessions.validate(&token, now));
+        assert!(!sessions.validate("0123456789abcdef0123456789abcdef", now));
+        assert!(!sessions.validate("", now));
+    }
+
+    #[test]
+    fn it_expires_idle_sessions() {
+        let now = Instant::now();
+        let mut sessions = SessionStore::new(2, IDLE);
+        let token = sessions.create(now).unwrap();
+
+        // Using the session keeps it alive
+        assert!(sessions.validate(&token, now + IDLE - Duration::from_secs(1)));
+        assert!(sessions.validate(&token, now + IDLE * 2 - Duration::from_secs(2)));
+
+        assert!(!sessions.validate(&token, now + IDLE * 3));
+    }
+
+    #[test]
+    fn it_pushes_out_the_oldest_session_when_full() {
+        let now = Instant::now();
+        let mut sessions = SessionStore::new(2, IDLE);
+        let first = sessions.create(now).unwrap();
+        let second = sessions.create(now + Duration::from_secs(1)).unwrap();
+        let third = sessions.create(now + Duration::from_secs(2)).unwrap();
+
+        let now = now + Duration::from_secs(3);
+        assert!(!sessions.validate(&first, now));
+        assert!(sessions.validate(&second, now));
+        assert!(sessions.validate(&third, now));
+    }
+
+    #[test]
+    fn it_revokes_sessions() {
+        let now = Instant::now();
+        let mut sessions = SessionStore::new(2, IDLE);
+        let first = sessions.create(now).unwrap();
+        let second = sessions.create(now).unwrap();
+
+        sessions.revoke(&first);
+        assert!(!sessions.validate(&first, now));
+        assert!(sessions.validate(&second, now));
+
+        sessions.clear();
+        assert!(!sessions.validate(&second, now));
+    }
+}
@@ -1,8 +1,25 @@
-use postcard::{from_bytes, to_vec};
+use log::error;
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
+use crate::auth::PasswordHash;
+use crate::secrets::SecretCipher;
 use crate::storage::{Storage, StorageError};
+use crate::wifi::{ApMode, KnownNetwork, WifiAuth, MAX_KNOWN_NETWORKS};
+
+mod export;
+mod form;
+mod hostname;
+mod patch;
+mod record;
+mod schema;
+mod static_ip;
+
+pub use export::{ConfigExport, ImportError};
+pub use form::{ConfigUpdate, ConfigView, PASSWORD_PLACEHOLDER};
+pub use hostname::{unique_hostname, validate_hostname, DEFAULT_HOSTNAME, MAX_HOSTNAME_LEN};
+pub use patch::{ConfigPatch, PatchError};
+pub use static_ip::{StaticIp, StaticIpConfig};
 
 #[toml_cfg::toml_config]
 struct DefaultConfig {
@@ -20,9 +37,16 @@ struct DefaultConfig {
 pub struct InternalConfig {
     wifi_ssid: String,
     wifi_pass: String,
+    wifi_auth: WifiAuth,
     tz: String,
     led_color: u32,
     hours_24: bool,
+    networks: Vec<KnownNetwork>,
+    static_ip: Option<StaticIp>,
+    ap_mode: ApMode,
+    ap_pass: String,
+    hostname: String,
+    admin_pass: Option<PasswordHash>,
 }
 
 impl Default for InternalConfig {
@@ -35,6 +59,7 @@ impl Default for InternalConfig {
             0x00000088,
             false,
         )
+        .with_ap_pass(wifi_config.ap_pass)
     }
 }
 
@@ -43,9 +68,16 @@ impl InternalConfig {
         InternalConfig {
             wifi_ssid: String::from(wifi_ssid),
             wifi_pass: String::from(wifi_pass),
+            wifi_auth: WifiAuth::Auto,
             tz: String::from(tz),
             led_color,
             hours_24,
+            networks: Vec::new(),
+            static_ip: None,
+            ap_mode: ApMode::default(),
+            ap_pass: String::new(),
+            hostname: String::new(),
+            admin_pass: None,
         }
     }
 
@@ -57,6 +89,15 @@ impl InternalConfig {
         &self.wifi_pass
     }
 
+    pub fn wifi_auth(&self) -> WifiAuth {
+        self.wifi_auth
+    }
+
+    pub fn with_wifi_auth(mut self, wifi_auth: WifiAuth) -> Self {
+        self.wifi_auth = wifi_auth;
+        self
+    }
+
     pub fn tz(&self) -> &str {
         &self.tz
     }
@@ -68,9 +109,167 @@ impl InternalConfig {
     pub fn hours_24(&self) -> bool {
         self.hours_24
     }
+
+    /// Fixed IPv4 settings, or `None` to use DHCP.
+    pub fn static_ip(&self) -> Option<StaticIp> {
+        self.static_ip
+    }
+
+    pub fn with_static_ip(mut self, static_ip: Option<StaticIp>) -> Self {
+        self.static_ip = static_ip;
+        self
+    }
+
+    pub fn ap_mode(&self) -> ApMode {
+        self.ap_mode
+    }
+
+    pub fn with_ap_mode(mut self, ap_mode: ApMode) -> Self {
+        self.ap_mode = ap_mode;
+        self
+    }
+
+    /// Password of the setup access point. Empty until one is generated on
+    /// first boot, and never part of the web form.
+    pub fn ap_pass(&self) -> &str {
+        &self.ap_pass
+    }
+
+    pub fn with_ap_pass(mut self, ap_pass: &str) -> Self {
+        self.ap_pass = String::from(ap_pass);
+        self
+    }
+
+    /// Hostname set by the user, or empty to use the default.
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        self.hostname = String::from(hostname);
+        self
+    }
+
+    /// The hostname the clock announces: the one set by the user, or the
+    /// default made unique with the MAC address.
+    pub fn hostname_or_default(&self, mac: &[u8; 6]) -> String {
+        if self.hostname.is_empty() {
+            unique_hostname(DEFAULT_HOSTNAME, mac)
+        } else {
+            self.hostname.clone()
+        }
+    }
+
+    /// Hash of the password for changing settings through the web server,
+    /// or `None` until the owner sets one. Never part of the web form.
+    pub fn admin_pass(&self) -> Option<&PasswordHash> {
+        self.admin_pass.as_ref()
+    }
+
+    pub fn with_admin_pass(mut self, admin_pass: Option<PasswordHash>) -> Self {
+        self.admin_pass = admin_pass;
+        self
+    }
+
+    /// Carries over what the settings form doesn't cover from `current`: the
+    /// saved networks and the access point and admin passwords.
+    pub fn with_device_fields_from(self, current: &InternalConfig) -> Self {
+        self.with_networks(current.networks().to_vec())
+            .with_ap_pass(current.ap_pass())
+            .with_admin_pass(current.admin_pass().cloned())
+    }
+
+    /// Networks saved in addition to the primary one.
+    pub fn networks(&self) -> &[KnownNetwork] {
+        &self.networks
+    }
+
+    pub fn with_networks(mut self, networks: Vec<KnownNetwork>) -> Self {
+        self.networks = networks;
+        self
+    }
+
+    /// Adds a network, or replaces the saved network with the same SSID.
+    /// Returns `false` if the list is already full.
+    pub fn save_network(&mut self, network: KnownNetwork) -> bool {
+        if let Some(existing) = self.networks.iter_mut().find(|n| n.ssid == network.ssid) {
+            *existing = network;
+        } else if self.networks.len() < MAX_KNOWN_NETWORKS {
+            self.networks.push(network);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Returns whether a network with the SSID was saved.
+    pub fn remove_network(&mut self, ssid: &str) -> bool {
+        let len = self.networks.len();
+        self.networks.retain(|n| n.ssid != ssid);
+        self.networks.len() != len
+    }
+
+    /// Whether joining a network would go differently with `other`.
+    pub fn same_network_settings(&self, other: &InternalConfig) -> bool {
+        self.candidate_networks() == other.candidate_networks() && self.static_ip == other.static_ip
+    }
+
+    /// Replaces everything that decides how the clock joins a network with
+    /// the settings of `other`.
+    pub fn with_network_settings_of(mut self, other: &InternalConfig) -> Self {
+        self.wifi_ssid = other.wifi_ssid.clone();
+        self.wifi_pass = other.wifi_pass.clone();
+        self.wifi_auth = other.wifi_auth;
+        self.networks = other.networks.clone();
+        self.static_ip = other.static_ip;
+        self
+    }
+
+    /// Every network the clock may join: the saved networks plus the primary
+    /// network at the lowest priority, unless it is also saved.
+    pub fn candidate_networks(&self) -> Vec<KnownNetwork> {
+        let mut candidates = self.networks.clone();
+        if !self.wifi_ssid.is_empty() && !candidates.iter().any(|n| n.ssid == self.wifi_ssid) {
+            candidates.push(
+                KnownNetwork::new(&self.wifi_ssid, &self.wifi_pass, 0).with_auth(self.wifi_auth),
+            );
+        }
+        candidates
+    }
 }
 
-fn validate_color(color: &str) -> Result<(), ValidationError> {
+/// Longest SSID the WiFi driver accepts, in bytes.
+pub const MAX_WIFI_SSID_LEN: usize = 32;
+/// Longest WPA passphrase (or raw hex key) the WiFi driver accepts, in bytes.
+pub const MAX_WIFI_PASS_LEN: usize = 64;
+pub const MAX_TIME_ZONE_LEN: usize = 64;
+
+pub(crate) fn validate_wifi_ssid(ssid: &str) -> Result<(), ValidationError> {
+    if ssid.len() > MAX_WIFI_SSID_LEN {
+        return Err(ValidationError::new("too_long"));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_wifi_pass(pass: &str) -> Result<(), ValidationError> {
+    if pass.len() > MAX_WIFI_PASS_LEN {
+        return Err(ValidationError::new("too_long"));
+    }
+    Ok(())
+}
+
+fn validate_wifi_auth(config: &Config) -> Result<(), ValidationError> {
+    config.wifi_auth.validate_password(&config.wifi_pass)
+}
+
+fn validate_time_zone(tz: &str) -> Result<(), ValidationError> {
+    if tz.len() > MAX_TIME_ZONE_LEN {
+        return Err(ValidationError::new("too_long"));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_color(color: &str) -> Result<(), ValidationError> {
     for (i, c) in color.chars().enumerate() {
         match (i, c) {
             (0, '#') => continue,
@@ -85,14 +284,32 @@ fn validate_color(color: &str) -> Result<(), ValidationError> {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Validate)]
+#[validate(schema(
+    function = "validate_wifi_auth",
+    message = "password does not suit the authentication method"
+))]
 pub struct Config {
-    #[validate(length(min = 1, message = "SSID must not be blank"))]
+    #[validate(
+        length(min = 1, message = "SSID must not be blank"),
+        custom(function = "validate_wifi_ssid", message = "SSID is too long")
+    )]
     #[serde(rename = "wifiSsid")]
     wifi_ssid: String,
-    #[validate(length(min = 0))]
+    #[validate(custom(function = "validate_wifi_pass", message = "password is too long"))]
     #[serde(rename = "wifiPass")]
     wifi_pass: String,
-    #[validate(length(min = 1, message = "time zone must not be blank"))]
+    // Left out when unset so backups made before it existed keep their
+    // checksum.
+    #[serde(
+        rename = "wifiAuth",
+        default,
+        skip_serializing_if = "WifiAuth::is_auto"
+    )]
+    wifi_auth: WifiAuth,
+    #[validate(
+        length(min = 1, message = "time zone must not be blank"),
+        custom(function = "validate_time_zone", message = "time zone is too long")
+    )]
     #[serde(rename = "timeZone")]
     time_zone: String,
     #[validate(
@@ -102,6 +319,18 @@ pub struct Config {
     #[serde(rename = "ledColor")]
     led_color: String,
     hours_24: bool,
+    #[validate(nested)]
+    #[serde(rename = "staticIp", default, skip_serializing_if = "Option::is_none")]
+    static_ip: Option<StaticIpConfig>,
+    #[serde(rename = "apMode", default, skip_serializing_if = "ApMode::is_default")]
+    ap_mode: ApMode,
+    /// `None` to use the default hostname.
+    #[validate(custom(
+        function = "validate_hostname",
+        message = "hostname must be letters, digits and hyphens"
+    ))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
 }
 
 impl Config {
@@ -115,12 +344,36 @@ impl Config {
         Config {
             wifi_ssid: String::from(wifi_ssid),
             wifi_pass: String::from(wifi_pass),
+            wifi_auth: WifiAuth::Auto,
             time_zone: String::from(time_zone),
             led_color: String::from(led_color),
             hours_24,
+            static_ip: None,
+            ap_mode: ApMode::default(),
+            hostname: None,
         }
     }
 
+    pub fn with_wifi_auth(mut self, wifi_auth: WifiAuth) -> Self {
+        self.wifi_auth = wifi_auth;
+        self
+    }
+
+    pub fn with_static_ip(mut self, static_ip: Option<StaticIpConfig>) -> Self {
+        self.static_ip = static_ip;
+        self
+    }
+
+    pub fn with_ap_mode(mut self, ap_mode: ApMode) -> Self {
+        self.ap_mode = ap_mode;
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: Option<&str>) -> Self {
+        self.hostname = hostname.map(String::from);
+        self
+    }
+
     pub fn validate(&self) -> Result<(), validator::ValidationErrors> {
         Validate::validate(self)
     }
@@ -131,9 +384,13 @@ impl From<InternalConfig> for Config {
         Config {
             wifi_ssid: item.wifi_ssid,
             wifi_pass: item.wifi_pass,
+            wifi_auth: item.wifi_auth,
             time_zone: item.tz,
             led_color: format!("#{:06x}", item.led_color),
             hours_24: item.hours_24,
+            static_ip: item.static_ip.map(StaticIpConfig::from),
+            ap_mode: item.ap_mode,
+            hostname: Some(item.hostname).filter(|h| !h.is_empty()),
         }
     }
 }
@@ -143,23 +400,51 @@ impl From<Config> for InternalConfig {
         InternalConfig {
             wifi_ssid: item.wifi_ssid,
             wifi_pass: item.wifi_pass,
+            wifi_auth: item.wifi_auth,
             tz: item.time_zone,
             led_color: u32::from_str_radix(&item.led_color.replace("#", ""), 16).unwrap_or(0),
             hours_24: item.hours_24,
+            networks: Vec::new(),
+            static_ip: item.static_ip.map(StaticIp::from),
+            ap_mode: item.ap_mode,
+            ap_pass: String::new(),
+            hostname: item.hostname.unwrap_or_default(),
+            admin_pass: None,
         }
     }
 }
 
+const CURRENT_SLOT: &str = "config";
+const PREVIOUS_SLOT: &str = "config_prev";
+
+/// Where the config returned by `ConfigStorage::load` came from.
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigSource {
+    /// Nothing usable was stored, so the defaults are in use.
+    Defaults,
+    /// The most recently saved config.
+    Current,
+    /// The most recent save was unreadable and the one before it was restored.
+    Backup,
+}
+
 pub struct ConfigStorage {
     storage: Box<dyn Storage + Send>,
+    cipher: Box<dyn SecretCipher + Send>,
     config: Option<InternalConfig>,
+    source: ConfigSource,
+    sequence: u32,
 }
 
 impl ConfigStorage {
-    pub fn new(storage: Box<dyn Storage + Send>) -> Self {
+    pub fn new(storage: Box<dyn Storage + Send>, cipher: Box<dyn SecretCipher + Send>) -> Self {
         ConfigStorage {
             storage,
+            cipher,
             config: None,
+            source: ConfigSource::Defaults,
+            sequence: 0,
         }
     }
 
@@ -168,35 +453,185 @@ impl ConfigStorage {
             return Ok(config.clone());
         }
 
-        let mut buf = [0; 256];
-        let config = match self.storage.get_raw("config", &mut buf) {
-            Ok(Some(v)) => from_bytes::<InternalConfig>(v).unwrap(),
-            _ => InternalConfig::default(),
+        let current = self.read_slot(CURRENT_SLOT)?;
+        let previous = self.read_slot(PREVIOUS_SLOT)?;
+
+        let (source, sequence, config) = match (current, previous) {
+            // Sequences wrap around, so the current slot is newer when it is
+            // less than half the range ahead
+            (Some((cur_seq, cur)), Some((prev_seq, _)))
+                if cur_seq.wrapping_sub(prev_seq) as i32 >= 0 =>
+            {
+                (ConfigSource::Current, cur_seq, cur)
+            }
+            // Only an interrupted save can leave the current slot behind
+            (Some(_), Some((prev_seq, prev))) => {
+                error!("Current config is older than the previous one, restored previous config");
+                (ConfigSource::Backup, prev_seq, prev)
+            }
+            (None, Some((prev_seq, prev))) => {
+                error!("Current config is unreadable, restored previous config");
+                (ConfigSource::Backup, prev_seq, prev)
+            }
+            (Some((cur_seq, cur)), None) => (ConfigSource::Current, cur_seq, cur),
+            (None, None) => (ConfigSource::Defaults, 0, InternalConfig::default()),
         };
+
+        self.source = source;
+        self.sequence = sequence;
         self.config = Some(config.clone());
 
         Ok(config)
     }
 
     pub fn save(&mut self, config: &InternalConfig) -> Result<(), StorageError> {
+        if self.config.is_none() {
+            self.load()?;
+        }
+
+        // Keep the last good config around in case this write is interrupted.
+        if let Ok(raw) = self.storage.get_vec(CURRENT_SLOT) {
+            if self.decode_slot(CURRENT_SLOT, &raw).is_some() {
+                self.storage.set_raw(PREVIOUS_SLOT, &raw)?;
+            }
+        }
+
+        let sequence = self.sequence.wrapping_add(1);
+        let payload = schema::encode(config, self.cipher.as_ref())
+            .map_err(|e| StorageError::WriteError(Box::new(e)))?;
         self.storage
-            .set_raw("config", &to_vec::<InternalConfig, 100>(config).unwrap())?;
+            .set_raw(CURRENT_SLOT, &record::encode(sequence, &payload))?;
 
+        self.source = ConfigSource::Current;
+        self.sequence = sequence;
         self.config = Some(config.clone());
         Ok(())
     }
+
+    /// Erases the whole storage namespace, not only the config slots, and
+    /// returns to the defaults.
+    pub fn factory_reset(&mut self) -> Result<InternalConfig, StorageError> {
+        self.storage.erase_all()?;
+
+        let config = InternalConfig::default();
+        self.source = ConfigSource::Defaults;
+        self.sequence = 0;
+        self.config = Some(config.clone());
+
+        Ok(config)
+    }
+
+    /// Reports whether the loaded config is the latest save, a backup or the
+    /// defaults.
+    pub fn source(&self) -> ConfigSource {
+        self.source
+    }
+
+    fn read_slot(&self, name: &str) -> Result<Option<(u32, InternalConfig)>, StorageError> {
+        match self.storage.get_vec(name) {
+            Ok(raw) => Ok(self.decode_slot(name, &raw)),
+            Err(StorageError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn decode_slot(&self, name: &str, raw: &[u8]) -> Option<(u32, InternalConfig)> {
+        // Configs saved before records existed have no checksum or sequence.
+        let (sequence, payload) = if record::is_record(raw) {
+            match record::decode(raw) {
+                Some(r) => (r.sequence, r.payload),
+                None => {
+                    error!("Stored config `{}` failed its checksum", name);
+                    return None;
+                }
+            }
+        } else {
+            (0, raw)
+        };
+
+        match schema::decode(payload, self.cipher.as_ref()) {
+            Ok(config) => Some((sequence, config)),
+            Err(e) => {
+                error!("Stored config `{}` is unreadable: {}", name, e);
+                None
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+
+    use crate::secrets::{ChaChaCipher, StaticKey};
     use crate::storage::InMemoryStorage;
 
     use super::*;
 
+    fn cipher() -> Box<dyn SecretCipher + Send> {
+        Box::new(ChaChaCipher::new(&StaticKey::new([7; 32])).unwrap())
+    }
+
+    /// Lets a test tamper with the storage after handing it to `ConfigStorage`.
+    #[derive(Clone)]
+    struct SharedStorage(Arc<Mutex<InMemoryStorage>>);
+
+    impl SharedStorage {
+        fn new() -> Self {
+            SharedStorage(Arc::new(Mutex::new(InMemoryStorage::new())))
+        }
+
+        fn corrupt(&self, name: &str) {
+            let mut raw = self.get_vec(name).unwrap();
+            let last = raw.len() - 1;
+            raw[last] ^= 0xff;
+            self.0.lock().unwrap().set_raw(name, &raw).unwrap();
+        }
+
+        fn truncate(&self, name: &str) {
+            let raw = self.get_vec(name).unwrap();
+            self.0
+                .lock()
+                .unwrap()
+                .set_raw(name, &raw[..raw.len() / 2])
+                .unwrap();
+        }
+    }
+
+    impl Storage for SharedStorage {
+        fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool, StorageError> {
+            self.0.lock().unwrap().set_raw(name, buf)
+        }
+
+        fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<&'a [u8], StorageError> {
+            self.0.lock().unwrap().get_raw(name, buf)
+        }
+
+        fn remove(&mut self, name: &str) -> Result<bool, StorageError> {
+            self.0.lock().unwrap().remove(name)
+        }
+
+        fn erase_all(&mut self) -> Result<(), StorageError> {
+            self.0.lock().unwrap().erase_all()
+        }
+    }
+
+    fn saved_twice(storage: &SharedStorage) -> (InternalConfig, InternalConfig) {
+        let first = InternalConfig::new("first", "pass", "US/Central", 0x111111, false);
+        let second = InternalConfig::new("second", "pass", "US/Central", 0x222222, true);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage.clone()), cipher());
+        config_storage.save(&first).unwrap();
+        config_storage.save(&second).unwrap();
+
+        (first, second)
+    }
+
     #[test]
     fn it_returns_default_if_no_value_stored() {
         let storage = InMemoryStorage::new();
-        let mut config_storage = ConfigStorage::new(Box::new(storage));
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
 
         let config = config_storage.load().unwrap();
 
@@ -206,7 +641,7 @@ mod tests {
     #[test]
     fn it_loads_previously_saved_config() {
         let storage = InMemoryStorage::new();
-        let mut config_storage = ConfigStorage::new(Box::new(storage));
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
 
         let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
         config_storage.save(&config).unwrap();
@@ -214,10 +649,219 @@ mod tests {
         assert_eq!(config, config_storage.load().unwrap());
     }
 
+    #[test]
+    fn it_returns_default_if_stored_config_is_unreadable() {
+        let mut storage = InMemoryStorage::new();
+        storage.set_raw("config", &[0xff, 0xff, 0xff]).unwrap();
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), InternalConfig::default());
+    }
+
+    #[test]
+    fn it_loads_config_saved_by_older_firmware() {
+        // Bare postcard bytes of the first layout, without header or checksum
+        let legacy = [
+            4, b's', b's', b'i', b'd', 4, b'p', b'a', b's', b's', 10, b'U', b'S', b'/', b'C', b'e',
+            b'n', b't', b'r', b'a', b'l', 0xd6, 0xe8, 0x48, 0,
+        ];
+        let mut storage = InMemoryStorage::new();
+        storage.set_raw("config", &legacy).unwrap();
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(
+            config_storage.load().unwrap(),
+            InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false)
+        );
+    }
+
+    #[test]
+    fn it_reports_source_of_loaded_config() {
+        let storage = SharedStorage::new();
+        let mut config_storage = ConfigStorage::new(Box::new(storage.clone()), cipher());
+        config_storage.load().unwrap();
+        assert_eq!(config_storage.source(), ConfigSource::Defaults);
+
+        saved_twice(&storage);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+        config_storage.load().unwrap();
+        assert_eq!(config_storage.source(), ConfigSource::Current);
+    }
+
+    #[test]
+    fn it_restores_previous_config_if_current_fails_checksum() {
+        let storage = SharedStorage::new();
+        let (first, _) = saved_twice(&storage);
+        storage.corrupt(CURRENT_SLOT);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), first);
+        assert_eq!(config_storage.source(), ConfigSource::Backup);
+    }
+
+    #[test]
+    fn it_restores_previous_config_if_current_is_truncated() {
+        let storage = SharedStorage::new();
+        let (first, _) = saved_twice(&storage);
+        storage.truncate(CURRENT_SLOT);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), first);
+        assert_eq!(config_storage.source(), ConfigSource::Backup);
+    }
+
+    #[test]
+    fn it_restores_previous_config_if_current_secrets_cannot_be_opened() {
+        let storage = SharedStorage::new();
+        let (first, second) = saved_twice(&storage);
+        let other_key = ChaChaCipher::new(&StaticKey::new([9; 32])).unwrap();
+        let payload = schema::encode(&second, &other_key).unwrap();
+        let mut writer = storage.clone();
+        writer
+            .set_raw(CURRENT_SLOT, &record::encode(2, &payload))
+            .unwrap();
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), first);
+        assert_eq!(config_storage.source(), ConfigSource::Backup);
+    }
+
+    #[test]
+    fn it_ignores_corrupt_previous_config() {
+        let storage = SharedStorage::new();
+        let (_, second) = saved_twice(&storage);
+        storage.corrupt(PREVIOUS_SLOT);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), second);
+        assert_eq!(config_storage.source(), ConfigSource::Current);
+    }
+
+    #[test]
+    fn it_returns_default_if_both_slots_are_corrupt() {
+        let storage = SharedStorage::new();
+        saved_twice(&storage);
+        storage.corrupt(CURRENT_SLOT);
+        storage.corrupt(PREVIOUS_SLOT);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), InternalConfig::default());
+        assert_eq!(config_storage.source(), ConfigSource::Defaults);
+    }
+
+    #[test]
+    fn it_restores_previous_config_if_it_is_newer() {
+        let storage = SharedStorage::new();
+        let older = InternalConfig::new("older", "pass", "US/Central", 0x111111, false);
+        let newer = InternalConfig::new("newer", "pass", "US/Central", 0x222222, false);
+        let mut writer = storage.clone();
+        for (slot, sequence, config) in [(PREVIOUS_SLOT, 2, &newer), (CURRENT_SLOT, 1, &older)] {
+            let payload = schema::encode(config, cipher().as_ref()).unwrap();
+            writer
+                .set_raw(slot, &record::encode(sequence, &payload))
+                .unwrap();
+        }
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), newer);
+        assert_eq!(config_storage.source(), ConfigSource::Backup);
+    }
+
+    #[test]
+    fn it_loads_the_newest_config_when_the_sequence_wraps() {
+        let storage = SharedStorage::new();
+        let older = InternalConfig::new("older", "pass", "US/Central", 0x111111, false);
+        let newer = InternalConfig::new("newer", "pass", "US/Central", 0x222222, false);
+        let mut writer = storage.clone();
+        for (slot, sequence, config) in
+            [(PREVIOUS_SLOT, u32::MAX, &older), (CURRENT_SLOT, 0, &newer)]
+        {
+            let payload = schema::encode(config, cipher().as_ref()).unwrap();
+            writer
+                .set_raw(slot, &record::encode(sequence, &payload))
+                .unwrap();
+        }
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage.clone()), cipher());
+        assert_eq!(config_storage.load().unwrap(), newer);
+        assert_eq!(config_storage.source(), ConfigSource::Current);
+
+        let third = InternalConfig::new("third", "pass", "US/Central", 0x333333, false);
+        config_storage.save(&third).unwrap();
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), third);
+    }
+
+    #[test]
+    fn it_does_not_overwrite_backup_with_corrupt_config() {
+        let storage = SharedStorage::new();
+        let (first, _) = saved_twice(&storage);
+        storage.corrupt(CURRENT_SLOT);
+
+        let third = InternalConfig::new("third", "pass", "US/Central", 0x333333, false);
+        let mut config_storage = ConfigStorage::new(Box::new(storage.clone()), cipher());
+        config_storage.load().unwrap();
+        config_storage.save(&third).unwrap();
+        storage.corrupt(CURRENT_SLOT);
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        assert_eq!(config_storage.load().unwrap(), first);
+        assert_eq!(config_storage.source(), ConfigSource::Backup);
+    }
+
+    #[test]
+    fn it_does_not_store_wifi_password_in_plain_text() {
+        let storage = SharedStorage::new();
+        let mut config_storage = ConfigStorage::new(Box::new(storage.clone()), cipher());
+
+        let config = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false);
+        config_storage.save(&config).unwrap();
+        config_storage.save(&config).unwrap();
+
+        for slot in [CURRENT_SLOT, PREVIOUS_SLOT] {
+            let raw = storage.get_vec(slot).unwrap();
+            assert!(!raw.windows(8).any(|w| w == b"hunter22"));
+        }
+    }
+
+    #[test]
+    fn it_erases_config_on_factory_reset() {
+        let mut storage = SharedStorage::new();
+        saved_twice(&storage);
+        storage.set_raw("other", &[1]).unwrap();
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage.clone()), cipher());
+        config_storage.load().unwrap();
+
+        assert_eq!(
+            config_storage.factory_reset().unwrap(),
+            InternalConfig::default()
+        );
+        assert_eq!(config_storage.load().unwrap(), InternalConfig::default());
+        assert_eq!(config_storage.source(), ConfigSource::Defaults);
+        assert!(matches!(
+            storage.get_vec("other"),
+            Err(StorageError::NotFound(_))
+        ));
+
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+        assert_eq!(config_storage.load().unwrap(), InternalConfig::default());
+        assert_eq!(config_storage.source(), ConfigSource::Defaults);
+    }
+
     #[test]
     fn it_updates_cache_on_save() {
         let storage = InMemoryStorage::new();
-        let mut config_storage = ConfigStorage::new(Box::new(storage));
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
 
         let config = config_storage.load().unwrap();
         assert_eq!(config, InternalConfig::default());
@@ -228,6 +872,93 @@ mod tests {
         assert_eq!(config, config_storage.load().unwrap());
     }
 
+    #[test]
+    fn it_saves_networks_by_ssid() {
+        let mut config = InternalConfig::default();
+
+        assert!(config.save_network(KnownNetwork::new("home", "pass", 1)));
+        assert!(config.save_network(KnownNetwork::new("office", "pass", 2)));
+        assert!(config.save_network(KnownNetwork::new("home", "new-pass", 3)));
+
+        assert_eq!(
+            config.networks(),
+            [
+                KnownNetwork::new("home", "new-pass", 3),
+                KnownNetwork::new("office", "pass", 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_limits_the_number_of_saved_networks() {
+        let mut config = InternalConfig::default();
+
+        for i in 0..MAX_KNOWN_NETWORKS {
+            assert!(config.save_network(KnownNetwork::new(&format!("net{}", i), "pass", 1)));
+        }
+
+        assert!(!config.save_network(KnownNetwork::new("one-more", "pass", 1)));
+        assert!(config.save_network(KnownNetwork::new("net0", "new-pass", 1)));
+    }
+
+    #[test]
+    fn it_removes_networks() {
+        let mut config = InternalConfig::default();
+        config.save_network(KnownNetwork::new("home", "pass", 1));
+
+        assert!(config.remove_network("home"));
+        assert!(!config.remove_network("home"));
+        assert!(config.networks().is_empty());
+    }
+
+    #[test]
+    fn it_includes_primary_network_in_candidates() {
+        let mut config = InternalConfig::new("primary", "pass", "US/Central", 0, false);
+        config.save_network(KnownNetwork::new("home", "pass", 1));
+
+        assert_eq!(
+            config.candidate_networks(),
+            [
+                KnownNetwork::new("home", "pass", 1),
+                KnownNetwork::new("primary", "pass", 0)
+            ]
+        );
+
+        config.save_network(KnownNetwork::new("primary", "pass", 5));
+        assert_eq!(config.candidate_networks(), config.networks());
+    }
+
+    #[test]
+    fn it_compares_network_settings() {
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
+
+        let other = InternalConfig::new("ssid", "pass", "US/Eastern", 0xff0000, true);
+        assert!(config.same_network_settings(&other));
+
+        let other = config.clone().with_wifi_auth(WifiAuth::Wpa2Personal);
+        assert!(!config.same_network_settings(&other));
+
+        let other = InternalConfig::new("ssid", "other-pass", "US/Central", 0x123456, false);
+        assert!(!config.same_network_settings(&other));
+        assert!(config
+            .clone()
+            .with_network_settings_of(&other)
+            .same_network_settings(&other));
+    }
+
+    #[test]
+    fn it_saves_and_loads_networks() {
+        let storage = InMemoryStorage::new();
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        let mut config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
+        config.save_network(KnownNetwork::new("home", "hunter22", 1));
+        config_storage.save(&config).unwrap();
+        config_storage.config = None;
+
+        assert_eq!(config, config_storage.load().unwrap());
+    }
+
     #[test]
     fn convert_internal_config_to_config() {
         let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
@@ -235,9 +966,13 @@ mod tests {
         let expected = Config {
             wifi_ssid: "ssid".to_string(),
             wifi_pass: "pass".to_string(),
+            wifi_auth: WifiAuth::Auto,
             time_zone: "US/Central".to_string(),
             led_color: "#123456".to_string(),
             hours_24: false,
+            static_ip: None,
+            ap_mode: ApMode::WhenDisconnected,
+            hostname: None,
         };
 
         assert_eq!(expected, config.into());
@@ -250,9 +985,13 @@ mod tests {
         let config = Config {
             wifi_ssid: "ssid".to_string(),
             wifi_pass: "pass".to_string(),
+            wifi_auth: WifiAuth::Auto,
             time_zone: "US/Central".to_string(),
             led_color: "#123456".to_string(),
             hours_24: false,
+            static_ip: None,
+            ap_mode: ApMode::WhenDisconnected,
+            hostname: None,
         };
 
         assert_eq!(expected, config.into());
@@ -303,6 +1042,60 @@ mod tests {
             .is_some());
     }
 
+    #[test]
+    fn it_saves_config_with_maximum_length_fields() {
+        let storage = InMemoryStorage::new();
+        let mut config_storage = ConfigStorage::new(Box::new(storage), cipher());
+
+        let ssid = "s".repeat(MAX_WIFI_SSID_LEN);
+        let pass = "p".repeat(MAX_WIFI_PASS_LEN);
+        let tz = "t".repeat(MAX_TIME_ZONE_LEN);
+        let config: Config = Config::new(&ssid, &pass, &tz, "#123456", true);
+        assert!(config.validate().is_ok());
+
+        let config = InternalConfig::from(config);
+        config_storage.save(&config).unwrap();
+
+        assert_eq!(config, config_storage.load().unwrap());
+    }
+
+    #[test]
+    fn validate_wifi_ssid_is_not_too_long() {
+        let ssid = "s".repeat(MAX_WIFI_SSID_LEN + 1);
+        let config: Config = Config::new(&ssid, "pass", "US/Central", "#123456", false);
+
+        let result = config.validate();
+        assert!(result.unwrap_err().field_errors().contains_key("wifi_ssid"));
+    }
+
+    #[test]
+    fn validate_wifi_ssid_length_is_in_bytes() {
+        // 11 three byte characters are 33 bytes, too long for the WiFi driver
+        let ssid = "\u{263a}".repeat(11);
+        let config: Config = Config::new(&ssid, "pass", "US/Central", "#123456", false);
+
+        let result = config.validate();
+        assert!(result.unwrap_err().field_errors().contains_key("wifi_ssid"));
+    }
+
+    #[test]
+    fn validate_wifi_pass_is_not_too_long() {
+        let pass = "p".repeat(MAX_WIFI_PASS_LEN + 1);
+        let config: Config = Config::new("ssid", &pass, "US/Central", "#123456", false);
+
+        let result = config.validate();
+        assert!(result.unwrap_err().field_errors().contains_key("wifi_pass"));
+    }
+
+    #[test]
+    fn validate_time_zone_is_not_too_long() {
+        let tz = "t".repeat(MAX_TIME_ZONE_LEN + 1);
+        let config: Config = Config::new("ssid", "pass", &tz, "#123456", false);
+
+        let result = config.validate();
+        assert!(result.unwrap_err().field_errors().contains_key("time_zone"));
+    }
+
     #[test]
     fn validate_color_starts_with_hash() {
         let config: Config = Config::new(
@@ -337,4 +1130,154 @@ mod tests {
         let error = result.unwrap_err();
         assert!(error.field_errors().get("led_color").is_some());
     }
+
+    #[test]
+    fn validate_wifi_pass_suits_wifi_auth() {
+        let raw_key = "0123456789abcdef".repeat(4);
+        let cases = [
+            (WifiAuth::Auto, "", true),
+            (WifiAuth::Auto, "pass", true),
+            (WifiAuth::Open, "", true),
+            (WifiAuth::Open, "password", false),
+            (WifiAuth::Wpa2Personal, "", false),
+            (WifiAuth::Wpa2Personal, "pass", false),
+            (WifiAuth::Wpa2Personal, "password", true),
+            (WifiAuth::Wpa2Personal, raw_key.as_str(), true),
+            (WifiAuth::Wpa2Wpa3Personal, "pass", false),
+            (WifiAuth::Wpa2Wpa3Personal, "password", true),
+            (WifiAuth::Wpa2Wpa3Personal, raw_key.as_str(), false),
+            (WifiAuth::Wpa3Personal, "pass", false),
+            (WifiAuth::Wpa3Personal, "password", true),
+            (WifiAuth::Wpa3Personal, raw_key.as_str(), false),
+        ];
+
+        for (auth, pass, valid) in cases {
+            let config =
+                Config::new("ssid", pass, "US/Central", "#123456", false).with_wifi_auth(auth);
+
+            let result = config.validate();
+            assert_eq!(result.is_ok(), valid, "{:?} with {:?}", auth, pass);
+            if let Err(e) = result {
+                assert!(e.field_errors().contains_key("__all__"));
+            }
+        }
+    }
+
+    #[test]
+    fn wifi_auth_defaults_to_auto() {
+        let json = r##"{"wifiSsid":"ssid","wifiPass":"pass","timeZone":"US/Central","ledColor":"#123456","hours_24":false}"##;
+
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(InternalConfig::from(config).wifi_auth(), WifiAuth::Auto);
+    }
+
+    #[test]
+    fn validate_static_ip() {
+        let static_ip = StaticIpConfig::new("10.0.0.5", "255.255.255.0", "10.0.1.1", "10.0.0.1");
+        let config = Config::new("ssid", "pass", "US/Central", "#123456", false)
+            .with_static_ip(Some(static_ip));
+
+        let result = config.validate();
+        assert!(result.unwrap_err().errors().contains_key("static_ip"));
+    }
+
+    #[test]
+    fn static_ip_defaults_to_dhcp() {
+        let json = r##"{"wifiSsid":"ssid","wifiPass":"pass","timeZone":"US/Central","ledColor":"#123456","hours_24":false}"##;
+
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(InternalConfig::from(config).static_ip(), None);
+    }
+
+    #[test]
+    fn it_reads_static_ip_from_json() {
+        let json = r##"{"wifiSsid":"ssid","wifiPass":"pass","timeZone":"US/Central","ledColor":"#123456","hours_24":false,
+            "staticIp":{"address":"10.0.0.5","netmask":"255.255.255.0","gateway":"10.0.0.1","dns":"10.0.0.1"}}"##;
+
+        let config: Config = serde_json::from_str(json).unwrap();
+        assert!(config.validate().is_ok());
+
+        let static_ip = InternalConfig::from(config).static_ip().unwrap();
+        assert_eq!(static_ip.address(), Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(static_ip.prefix_len(), 24);
+    }
+
+    #[test]
+    fn ap_mode_defaults_to_when_disconnected() {
+        let json = r##"{"wifiSsid":"ssid","wifiPass":"pass","timeZone":"US/Central","ledColor":"#123456","hours_24":false}"##;
+
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            InternalConfig::from(config).ap_mode(),
+            ApMode::WhenDisconnected
+        );
+    }
+
+    #[test]
+    fn it_leaves_the_access_point_password_out_of_the_form() {
+        let config = InternalConfig::default()
+            .with_ap_mode(ApMode::Never)
+            .with_ap_pass("31415926");
+
+        let json = serde_json::to_string(&Config::from(config)).unwrap();
+
+        assert!(json.contains(r#""apMode":"never""#));
+        assert!(!json.contains("31415926"));
+    }
+
+    #[test]
+    fn validate_hostname_is_a_dns_label() {
+        let config = Config::new("ssid", "pass", "US/Central", "#123456", false)
+            .with_hostname(Some("kitchen.clock"));
+
+        let result = config.validate();
+        assert!(result.unwrap_err().field_errors().contains_key("hostname"));
+
+        let config = Config::new("ssid", "pass", "US/Central", "#123456", false)
+            .with_hostname(Some("kitchen-clock"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn it_uses_a_unique_default_hostname() {
+        let mac = [0x24, 0x0a, 0xc4, 0x1a, 0x2b, 0x3c];
+        let config = InternalConfig::default();
+
+        assert_eq!(config.hostname_or_default(&mac), "nixie-clock-1a2b3c");
+        assert_eq!(
+            config.with_hostname("kitchen").hostname_or_default(&mac),
+            "kitchen"
+        );
+    }
+
+    #[test]
+    fn it_leaves_an_unset_hostname_out_of_the_form() {
+        let json = serde_json::to_string(&Config::from(InternalConfig::default())).unwrap();
+        assert!(!json.contains("hostname"));
+
+        let config = InternalConfig::default().with_hostname("kitchen");
+        let json = serde_json::to_string(&Config::from(config.clone())).unwrap();
+        assert!(json.contains(r#""hostname":"kitchen""#));
+
+        let form: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(InternalConfig::from(form), config);
+    }
+
+    #[test]
+    fn it_saves_the_wifi_auth() {
+        let mut config_storage = ConfigStorage::new(Box::new(InMemoryStorage::new()), cipher());
+        let config = InternalConfig::new("ssid", "password", "US/Central", 0x123456, false)
+            .with_wifi_auth(WifiAuth::Wpa3Personal);
+
+        config_storage.save(&config).unwrap();
+        config_storage.config = None;
+
+        assert_eq!(
+            config_storage.load().unwrap().wifi_auth(),
+            WifiAuth::Wpa3Personal
+        );
+    }
 }
@@ -0,0 +1,314 @@
+//! Config backups
+//!
+//! An export is a JSON document holding the web form of the config along
+//! with the versions it was written with and a SHA-256 checksum of the
+//! config and saved networks, so a hand-edited or truncated backup is
+//! rejected on import. Secrets can be left out, in which case importing keeps
+//! the current ones.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::schema::CURRENT_VERSION;
+use super::{Config, InternalConfig};
+use crate::wifi::KnownNetwork;
+
+const FORMAT: &str = "nixie-clock-config";
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("backup is not valid JSON: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("not a nixie clock config backup")]
+    UnknownFormat,
+    #[error("backup format version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("backup was written by newer firmware (config version {0})")]
+    NewerSchema(u8),
+    #[error("backup checksum does not match its contents")]
+    ChecksumMismatch,
+    #[error("backup contains an invalid config")]
+    Invalid(#[from] validator::ValidationErrors),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ConfigExport {
+    format: String,
+    version: u32,
+    #[serde(rename = "schemaVersion")]
+    schema_version: u8,
+    #[serde(rename = "includesSecrets")]
+    includes_secrets: bool,
+    config: Config,
+    #[serde(default)]
+    networks: Vec<KnownNetwork>,
+    checksum: String,
+}
+
+impl ConfigExport {
+    pub fn new(config: &InternalConfig, include_secrets: bool) -> Self {
+        let mut networks = config.networks().to_vec();
+        let mut config = Config::from(config.clone());
+        if !include_secrets {
+            config.wifi_pass = String::new();
+            networks.iter_mut().for_each(|n| n.pass = String::new());
+        }
+
+        ConfigExport {
+            format: String::from(FORMAT),
+            version: EXPORT_VERSION,
+            schema_version: CURRENT_VERSION,
+            includes_secrets: include_secrets,
+            checksum: checksum(&config, &networks),
+            config,
+            networks,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Checks a backup and returns the config it holds. If the backup was
+    /// exported without secrets, the passwords of `current` are kept for the
+    /// networks the two have in common.
+    pub fn import(json: &[u8], current: &InternalConfig) -> Result<InternalConfig, ImportError> {
+        let export: ConfigExport = serde_json::from_slice(json)?;
+
+        if export.format != FORMAT {
+            return Err(ImportError::UnknownFormat);
+        }
+        if export.version > EXPORT_VERSION {
+            return Err(ImportError::UnsupportedVersion(export.version));
+        }
+        if export.schema_version > CURRENT_VERSION {
+            return Err(ImportError::NewerSchema(export.schema_version));
+        }
+        if checksum(&export.config, &export.networks) != export.checksum {
+            return Err(ImportError::ChecksumMismatch);
+        }
+
+        let mut config = export.config;
+        let mut networks = export.networks;
+        if !export.includes_secrets {
+            // A password is only any use for the network it belongs to
+            config.wifi_pass = if config.wifi_ssid == current.wifi_ssid {
+                current.wifi_pass.clone()
+            } else {
+                String::new()
+            };
+            for network in networks.iter_mut() {
+                network.pass = current
+                    .networks()
+                    .iter()
+                    .find(|n| n.ssid == network.ssid)
+                    .map(|n| n.pass.clone())
+                    .unwrap_or_default();
+            }
+        }
+        config.validate()?;
+        for network in &networks {
+            network.validate()?;
+        }
+
+        // The access point and admin passwords belong to the device, not
+        // the backup
+        Ok(InternalConfig::from(config)
+            .with_networks(networks)
+            .with_ap_pass(current.ap_pass())
+            .with_admin_pass(current.admin_pass().cloned()))
+    }
+}
+
+fn checksum(config: &Config, networks: &[KnownNetwork]) -> String {
+    // Serialising a `Config` always produces its fields in the same order,
+    // so the JSON is a stable input for the digest. Networks are only hashed
+    // when there are some, so backups made before they existed still match.
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(config).unwrap());
+    if !networks.is_empty() {
+        hasher.update(serde_json::to_vec(networks).unwrap());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> InternalConfig {
+        InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, true)
+    }
+
+    #[test]
+    fn it_round_trips_a_backup() {
+        let json = ConfigExport::new(&config(), true).to_json().unwrap();
+
+        let current = InternalConfig::default();
+        let imported = ConfigExport::import(json.as_bytes(), &current).unwrap();
+
+        assert_eq!(imported, config());
+    }
+
+    #[test]
+    fn it_leaves_out_secrets_when_asked() {
+        let json = ConfigExport::new(&config(), false).to_json().unwrap();
+
+        assert!(!json.contains("hunter22"));
+    }
+
+    #[test]
+    fn it_keeps_current_secrets_when_importing_without_secrets() {
+        let json = ConfigExport::new(&config(), false).to_json().unwrap();
+
+        let current = InternalConfig::new("ssid", "current-pass", "US/Eastern", 0, false);
+        let imported = ConfigExport::import(json.as_bytes(), &current).unwrap();
+
+        assert_eq!(imported.wifi_ssid(), "ssid");
+        assert_eq!(imported.wifi_pass(), "current-pass");
+    }
+
+    #[test]
+    fn it_clears_the_password_of_another_network_when_importing_without_secrets() {
+        let json = ConfigExport::new(&config(), false).to_json().unwrap();
+
+        let current = InternalConfig::new("other", "current-pass", "US/Eastern", 0, false);
+        let imported = ConfigExport::import(json.as_bytes(), &current).unwrap();
+
+        assert_eq!(imported.wifi_ssid(), "ssid");
+        assert_eq!(imported.wifi_pass(), "");
+    }
+
+    #[test]
+    fn it_round_trips_saved_networks() {
+        let config = config().with_networks(vec![KnownNetwork::new("home", "home-pass", 2)]);
+        let json = ConfigExport::new(&config, true).to_json().unwrap();
+
+        let imported = ConfigExport::import(json.as_bytes(), &InternalConfig::default()).unwrap();
+
+        assert_eq!(imported, config);
+    }
+
+    #[test]
+    fn it_keeps_current_network_secrets_when_importing_without_secrets() {
+        let config = config().with_networks(vec![
+            KnownNetwork::new("home", "home-pass", 2),
+            KnownNetwork::new("office", "office-pass", 1),
+        ]);
+        let json = ConfigExport::new(&config, false).to_json().unwrap();
+        assert!(!json.contains("home-pass"));
+
+        let saved = vec![KnownNetwork::new("home", "current-pass", 0)];
+        let current = InternalConfig::default().with_networks(saved);
+        let imported = ConfigExport::import(json.as_bytes(), &current).unwrap();
+
+        assert_eq!(imported.networks()[0].pass, "current-pass");
+        assert_eq!(imported.networks()[1].pass, "");
+    }
+
+    #[test]
+    fn it_imports_backups_made_before_wifi_auth_was_configurable() {
+        let json = r##"{
+  "format": "nixie-clock-config",
+  "version": 1,
+  "schemaVersion": 2,
+  "includesSecrets": true,
+  "config": {
+    "wifiSsid": "ssid",
+    "wifiPass": "hunter22",
+    "timeZone": "US/Central",
+    "ledColor": "#123456",
+    "hours_24": true
+  },
+  "checksum": "200826f1a4a8e888de68361b501de0744795a3a4a92622903191c4d39f449f06"
+}"##;
+        let imported = ConfigExport::import(json.as_bytes(), &InternalConfig::default()).unwrap();
+
+        assert_eq!(imported, config());
+    }
+
+    #[test]
+    fn it_keeps_the_access_point_password_of_the_device() {
+        let json = ConfigExport::new(&config().with_ap_pass("11111111"), true)
+            .to_json()
+            .unwrap();
+        assert!(!json.contains("11111111"));
+
+        let current = InternalConfig::default().with_ap_pass("22222222");
+        let imported = ConfigExport::import(json.as_bytes(), &current).unwrap();
+
+        assert_eq!(imported.ap_pass(), "22222222");
+    }
+
+    #[test]
+    fn it_rejects_an_edited_backup() {
+        let json = ConfigExport::new(&config(), true).to_json().unwrap();
+        let json = json.replace("US/Central", "US/Eastern");
+
+        assert!(matches!(
+            ConfigExport::import(json.as_bytes(), &config()),
+            Err(ImportError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn it_rejects_malformed_json() {
+        assert!(matches!(
+            ConfigExport::import(b"{\"format\":", &config()),
+            Err(ImportError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn it_rejects_other_documents() {
+        let mut export = ConfigExport::new(&config(), true);
+        export.format = String::from("something-else");
+        let json = export.to_json().unwrap();
+
+        assert!(matches!(
+            ConfigExport::import(json.as_bytes(), &config()),
+            Err(ImportError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn it_rejects_newer_export_versions() {
+        let mut export = ConfigExport::new(&config(), true);
+        export.version = EXPORT_VERSION + 1;
+        let json = export.to_json().unwrap();
+
+        assert!(matches!(
+            ConfigExport::import(json.as_bytes(), &config()),
+            Err(ImportError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn it_rejects_backups_from_newer_firmware() {
+        let mut export = ConfigExport::new(&config(), true);
+        export.schema_version = CURRENT_VERSION + 1;
+        let json = export.to_json().unwrap();
+
+        assert!(matches!(
+            ConfigExport::import(json.as_bytes(), &config()),
+            Err(ImportError::NewerSchema(_))
+        ));
+    }
+
+    #[test]
+    fn it_validates_the_imported_config() {
+        let invalid = InternalConfig::new("", "hunter22", "US/Central", 0x123456, true);
+        let json = ConfigExport::new(&invalid, true).to_json().unwrap();
+
+        assert!(matches!(
+            ConfigExport::import(json.as_bytes(), &config()),
+            Err(ImportError::Invalid(_))
+        ));
+    }
+}
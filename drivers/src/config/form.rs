@@ -0,0 +1,190 @@
+//! Reading and writing the config form
+//!
+//! The config page never gets the WiFi password back. It is told whether
+//! one is set instead, and when it saves, a password that was left out or
+//! still holds the placeholder keeps the one the clock already has.
+
+use serde::{Deserialize, Serialize};
+
+use super::{Config, InternalConfig, StaticIpConfig};
+use crate::wifi::{ApMode, WifiAuth};
+
+/// Shown in place of a password that is set. Saving it keeps the password.
+pub const PASSWORD_PLACEHOLDER: &str = "********";
+
+/// The config as the config page reads it.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct ConfigView {
+    #[serde(rename = "wifiSsid")]
+    wifi_ssid: String,
+    #[serde(rename = "hasPassword")]
+    has_password: bool,
+    #[serde(rename = "wifiAuth")]
+    wifi_auth: WifiAuth,
+    #[serde(rename = "timeZone")]
+    time_zone: String,
+    #[serde(rename = "ledColor")]
+    led_color: String,
+    hours_24: bool,
+    #[serde(rename = "staticIp", skip_serializing_if = "Option::is_none")]
+    static_ip: Option<StaticIpConfig>,
+    #[serde(rename = "apMode")]
+    ap_mode: ApMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+}
+
+impl From<InternalConfig> for ConfigView {
+    fn from(item: InternalConfig) -> Self {
+        let config = Config::from(item);
+        ConfigView {
+            has_password: !config.wifi_pass.is_empty(),
+            wifi_ssid: config.wifi_ssid,
+            wifi_auth: config.wifi_auth,
+            time_zone: config.time_zone,
+            led_color: config.led_color,
+            hours_24: config.hours_24,
+            static_ip: config.static_ip,
+            ap_mode: config.ap_mode,
+            hostname: config.hostname,
+        }
+    }
+}
+
+/// The config as the config page saves it.
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct ConfigUpdate {
+    #[serde(rename = "wifiSsid")]
+    wifi_ssid: String,
+    /// `None` or the placeholder to keep the current password.
+    #[serde(rename = "wifiPass", default)]
+    wifi_pass: Option<String>,
+    #[serde(rename = "wifiAuth", default)]
+    wifi_auth: WifiAuth,
+    #[serde(rename = "timeZone")]
+    time_zone: String,
+    #[serde(rename = "ledColor")]
+    led_color: String,
+    hours_24: bool,
+    #[serde(rename = "staticIp", default)]
+    static_ip: Option<StaticIpConfig>,
+    #[serde(rename = "apMode", default)]
+    ap_mode: ApMode,
+    #[serde(default)]
+    hostname: Option<String>,
+}
+
+impl ConfigUpdate {
+    /// Fills in the password from `current` where the update keeps it. The
+    /// result still needs validating.
+    pub fn merge(self, current: &InternalConfig) -> Config {
+        let wifi_pass = match self.wifi_pass {
+            Some(pass) if pass != PASSWORD_PLACEHOLDER => pass,
+            _ => current.wifi_pass.clone(),
+        };
+
+        Config {
+            wifi_ssid: self.wifi_ssid,
+            wifi_pass,
+            wifi_auth: self.wifi_auth,
+            time_zone: self.time_zone,
+            led_color: self.led_color,
+            hours_24: self.hours_24,
+            static_ip: self.static_ip,
+            ap_mode: self.ap_mode,
+            hostname: self.hostname,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> InternalConfig {
+        InternalConfig::new("home", "hunter22", "US/Central", 0x123456, false)
+    }
+
+    fn update(wifi_pass: &str) -> ConfigUpdate {
+        let json = format!(
+            r##"{{"wifiSsid":"home","timeZone":"US/Eastern","ledColor":"#ff0000","hours_24":true{}}}"##,
+            wifi_pass
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn it_leaves_the_password_out_of_the_view() {
+        let json = serde_json::to_string(&ConfigView::from(current())).unwrap();
+
+        assert!(!json.contains("hunter22"));
+        assert!(!json.contains("wifiPass"));
+        assert!(json.contains(r#""hasPassword":true"#));
+    }
+
+    #[test]
+    fn it_tells_when_no_password_is_set() {
+        let config = InternalConfig::new("cafe", "", "US/Central", 0x123456, false);
+
+        let json = serde_json::to_string(&ConfigView::from(config)).unwrap();
+
+        assert!(json.contains(r#""hasPassword":false"#));
+    }
+
+    #[test]
+    fn it_keeps_the_password_when_left_out() {
+        let config = update("").merge(&current());
+
+        assert_eq!(
+            InternalConfig::from(config),
+            InternalConfig::new("home", "hunter22", "US/Eastern", 0xff0000, true)
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_password_when_null() {
+        let config = update(r#","wifiPass":null"#).merge(&current());
+
+        assert_eq!(config.wifi_pass, "hunter22");
+    }
+
+    #[test]
+    fn it_keeps_the_password_when_given_the_placeholder() {
+        let config = update(r#","wifiPass":"********""#).merge(&current());
+
+        assert_eq!(config.wifi_pass, "hunter22");
+    }
+
+    #[test]
+    fn it_replaces_the_password_when_given_a_new_one() {
+        let config = update(r#","wifiPass":"correct horse""#).merge(&current());
+
+        assert_eq!(config.wifi_pass, "correct horse");
+    }
+
+    #[test]
+    fn it_clears_the_password_when_given_an_empty_one() {
+        let config = update(r#","wifiPass":"","wifiAuth":"open""#).merge(&current());
+
+        assert_eq!(config.wifi_pass, "");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn it_keeps_the_password_when_only_the_ssid_changes() {
+        let json = r##"{"wifiSsid":"home-5g","timeZone":"US/Central","ledColor":"#123456","hours_24":false}"##;
+        let update: ConfigUpdate = serde_json::from_str(json).unwrap();
+
+        let config = update.merge(&current());
+
+        assert_eq!(config.wifi_ssid, "home-5g");
+        assert_eq!(config.wifi_pass, "hunter22");
+    }
+
+    #[test]
+    fn it_validates_the_merged_password() {
+        let config = update(r#","wifiAuth":"open""#).merge(&current());
+
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Device hostname
+//!
+//! The clock announces itself on the network as `<hostname>.local`. A clock
+//! without a hostname of its own uses `nixie-clock` followed by the end of
+//! its MAC address, so several clocks on one network can be told apart.
+
+use validator::ValidationError;
+
+/// Start of the hostname a clock uses until it is given one.
+pub const DEFAULT_HOSTNAME: &str = "nixie-clock";
+/// Longest label DNS allows, in bytes.
+pub const MAX_HOSTNAME_LEN: usize = 63;
+/// Number of MAC address bytes appended to the default hostname.
+const SUFFIX_BYTES: usize = 3;
+
+/// Accepts a single DNS label: letters, digits and hyphens, not starting or
+/// ending with a hyphen.
+pub fn validate_hostname(hostname: &str) -> Result<(), ValidationError> {
+    if hostname.is_empty() || hostname.len() > MAX_HOSTNAME_LEN {
+        return Err(ValidationError::new("invalid_length"));
+    }
+    if hostname.starts_with('-') || hostname.ends_with('-') {
+        return Err(ValidationError::new("invalid_hyphen"));
+    }
+    if !hostname
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    {
+        return Err(ValidationError::new("invalid_character"));
+    }
+    Ok(())
+}
+
+/// Appends the last bytes of `mac` to `base`, shortening `base` if that
+/// would make the label too long.
+pub fn unique_hostname(base: &str, mac: &[u8; 6]) -> String {
+    let suffix: String = mac[mac.len() - SUFFIX_BYTES..]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let max_base = MAX_HOSTNAME_LEN - suffix.len() - 1;
+    let base = base[..base.len().min(max_base)].trim_end_matches('-');
+    format!("{}-{}", base, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x24, 0x0a, 0xc4, 0x1a, 0x2b, 0x3c];
+
+    #[test]
+    fn it_accepts_dns_labels() {
+        for good in ["nixie-clock", "kitchen", "Clock2", "a", &"a".repeat(63)] {
+            assert!(validate_hostname(good).is_ok(), "{:?}", good);
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_labels() {
+        for bad in [
+            "",
+            &"a".repeat(64),
+            "-clock",
+            "clock-",
+            "nixie.clock",
+            "nixie_clock",
+            "nixie clock",
+            "uhr-ö",
+        ] {
+            assert!(validate_hostname(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn it_appends_the_end_of_the_mac_address() {
+        assert_eq!(
+            unique_hostname(DEFAULT_HOSTNAME, &MAC),
+            "nixie-clock-1a2b3c"
+        );
+    }
+
+    #[test]
+    fn it_keeps_unique_hostnames_within_a_label() {
+        let hostname = unique_hostname(&"a".repeat(63), &MAC);
+
+        assert_eq!(hostname.len(), MAX_HOSTNAME_LEN);
+        assert!(hostname.ends_with("a-1a2b3c"));
+        assert!(validate_hostname(&hostname).is_ok());
+
+        // Shortening must not leave a double hyphen
+        let base = format!("{}-b", "a".repeat(55));
+        assert_eq!(
+            unique_hostname(&base, &MAC),
+            format!("{}-1a2b3c", "a".repeat(55))
+        );
+    }
+}
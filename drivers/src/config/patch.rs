@@ -0,0 +1,245 @@
+//! Partial config updates
+//!
+//! A patch is a JSON object with any of the fields of the config form. The
+//! fields it leaves out keep their stored values, so a client that doesn't
+//! know about a field can't reset it. Fields that can be unset, like the
+//! static IP settings, are unset with `null`; the rest can't be `null`.
+
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use super::form::PASSWORD_PLACEHOLDER;
+use super::{Config, InternalConfig, StaticIpConfig};
+use crate::wifi::{ApMode, WifiAuth};
+
+#[derive(Error, Debug)]
+pub enum PatchError {
+    #[error("patch is not a valid config: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("patched config is invalid")]
+    Invalid(#[from] validator::ValidationErrors),
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigPatch {
+    #[serde(rename = "wifiSsid", default, deserialize_with = "present")]
+    wifi_ssid: Option<String>,
+    /// The placeholder keeps the current password, like leaving it out.
+    #[serde(rename = "wifiPass", default, deserialize_with = "present")]
+    wifi_pass: Option<String>,
+    #[serde(rename = "wifiAuth", default, deserialize_with = "present")]
+    wifi_auth: Option<WifiAuth>,
+    #[serde(rename = "timeZone", default, deserialize_with = "present")]
+    time_zone: Option<String>,
+    #[serde(rename = "ledColor", default, deserialize_with = "present")]
+    led_color: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    hours_24: Option<bool>,
+    #[serde(rename = "staticIp", default, deserialize_with = "nullable")]
+    static_ip: Option<Option<StaticIpConfig>>,
+    #[serde(rename = "apMode", default, deserialize_with = "present")]
+    ap_mode: Option<ApMode>,
+    #[serde(default, deserialize_with = "nullable")]
+    hostname: Option<Option<String>>,
+}
+
+impl ConfigPatch {
+    /// Applies the patch in `json` to `current` and returns the result if it
+    /// is valid. Nothing is changed unless the whole patch applies.
+    pub fn apply(json: &[u8], current: &InternalConfig) -> Result<InternalConfig, PatchError> {
+        // Structs also deserialize from arrays, which aren't a patch
+        let fields: Map<String, Value> = serde_json::from_slice(json)?;
+        let patch = ConfigPatch::deserialize(Value::Object(fields))?;
+        let config = patch.merge(current);
+        config.validate()?;
+
+        // Saved networks and the device passwords aren't part of the form
+        Ok(InternalConfig::from(config).with_device_fields_from(current))
+    }
+
+    fn merge(self, current: &InternalConfig) -> Config {
+        let mut config = Config::from(current.clone());
+
+        if let Some(wifi_ssid) = self.wifi_ssid {
+            config.wifi_ssid = wifi_ssid;
+        }
+        if let Some(wifi_pass) = self.wifi_pass.filter(|p| p != PASSWORD_PLACEHOLDER) {
+            config.wifi_pass = wifi_pass;
+        }
+        if let Some(wifi_auth) = self.wifi_auth {
+            config.wifi_auth = wifi_auth;
+        }
+        if let Some(time_zone) = self.time_zone {
+            config.time_zone = time_zone;
+        }
+        if let Some(led_color) = self.led_color {
+            config.led_color = led_color;
+        }
+        if let Some(hours_24) = self.hours_24 {
+            config.hours_24 = hours_24;
+        }
+        if let Some(static_ip) = self.static_ip {
+            config.static_ip = static_ip;
+        }
+        if let Some(ap_mode) = self.ap_mode {
+            config.ap_mode = ap_mode;
+        }
+        if let Some(hostname) = self.hostname {
+            config.hostname = hostname;
+        }
+        config
+    }
+}
+
+/// Refuses `null` for fields that can't be unset. Left out, they are `None`
+/// through `#[serde(default)]`.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Tells a field set to `null` (`Some(None)`) apart from one left out
+/// (`None`, through `#[serde(default)]`).
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::auth::PasswordHash;
+    use crate::config::StaticIp;
+    use crate::wifi::KnownNetwork;
+
+    fn current() -> InternalConfig {
+        InternalConfig::new("home", "hunter22", "US/Central", 0x123456, false)
+            .with_wifi_auth(WifiAuth::Wpa2Personal)
+            .with_ap_mode(ApMode::Never)
+            .with_hostname("kitchen")
+    }
+
+    fn apply(json: &str) -> Result<InternalConfig, PatchError> {
+        ConfigPatch::apply(json.as_bytes(), &current())
+    }
+
+    #[test]
+    fn it_changes_only_the_fields_given() {
+        let config = apply(r##"{"ledColor":"#ff0000","hours_24":true}"##).unwrap();
+
+        assert_eq!(
+            config,
+            InternalConfig::new("home", "hunter22", "US/Central", 0xff0000, true)
+                .with_wifi_auth(WifiAuth::Wpa2Personal)
+                .with_ap_mode(ApMode::Never)
+                .with_hostname("kitchen")
+        );
+    }
+
+    #[test]
+    fn it_keeps_everything_for_an_empty_patch() {
+        assert_eq!(apply("{}").unwrap(), current());
+    }
+
+    #[test]
+    fn it_keeps_what_is_not_in_the_form() {
+        let admin_pass = PasswordHash::new("correct horse", 10).unwrap();
+        let current = current()
+            .with_networks(vec![KnownNetwork::new("office", "office-pass", 1)])
+            .with_ap_pass("31415926")
+            .with_admin_pass(Some(admin_pass.clone()));
+
+        let config = ConfigPatch::apply(br#"{"hours_24":true}"#, &current).unwrap();
+
+        assert_eq!(config.networks(), current.networks());
+        assert_eq!(config.ap_pass(), "31415926");
+        assert_eq!(config.admin_pass(), Some(&admin_pass));
+    }
+
+    #[test]
+    fn it_keeps_the_password_for_the_placeholder() {
+        let config = apply(r#"{"wifiPass":"********"}"#).unwrap();
+        assert_eq!(config.wifi_pass(), "hunter22");
+
+        let config = apply(r#"{"wifiPass":"correct horse"}"#).unwrap();
+        assert_eq!(config.wifi_pass(), "correct horse");
+    }
+
+    #[test]
+    fn it_sets_and_clears_nullable_fields() {
+        let config = apply(
+            r#"{"staticIp":{"address":"10.0.0.5","netmask":"255.255.255.0","gateway":"10.0.0.1","dns":"10.0.0.1"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.static_ip(),
+            Some(StaticIp::new(
+                Ipv4Addr::new(10, 0, 0, 5),
+                Ipv4Addr::new(255, 255, 255, 0),
+                Ipv4Addr::new(10, 0, 0, 1),
+                Ipv4Addr::new(10, 0, 0, 1),
+            ))
+        );
+
+        let config = apply(r#"{"hostname":null}"#).unwrap();
+        assert_eq!(config.hostname(), "");
+        assert_eq!(config.static_ip(), None);
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields() {
+        let result = apply(r#"{"hours_24":true,"brightness":50}"#);
+
+        assert!(
+            matches!(result, Err(PatchError::Malformed(ref e)) if e.to_string().contains("brightness"))
+        );
+    }
+
+    #[test]
+    fn it_rejects_fields_of_the_wrong_type() {
+        assert!(matches!(
+            apply(r#"{"hours_24":"yes"}"#),
+            Err(PatchError::Malformed(_))
+        ));
+        assert!(matches!(
+            apply(r#"{"apMode":"sometimes"}"#),
+            Err(PatchError::Malformed(_))
+        ));
+        assert!(matches!(
+            apply(r#"{"wifiSsid":null}"#),
+            Err(PatchError::Malformed(_))
+        ));
+        assert!(matches!(apply("[]"), Err(PatchError::Malformed(_))));
+    }
+
+    #[test]
+    fn it_rejects_the_whole_patch_if_a_field_is_invalid() {
+        let result = apply(r#"{"hours_24":true,"ledColor":"red"}"#);
+
+        let Err(PatchError::Invalid(errors)) = result else {
+            panic!("expected a validation error, got {:?}", result);
+        };
+        assert!(errors.field_errors().contains_key("led_color"));
+        assert_eq!(errors.field_errors().len(), 1);
+    }
+
+    #[test]
+    fn it_validates_fields_against_the_stored_ones() {
+        // Fine on its own, but the stored password doesn't suit an open network
+        let result = apply(r#"{"wifiAuth":"open"}"#);
+        assert!(matches!(result, Err(PatchError::Invalid(_))));
+
+        let config = apply(r#"{"wifiAuth":"open","wifiPass":""}"#).unwrap();
+        assert_eq!(config.wifi_auth(), WifiAuth::Open);
+    }
+}
@@ -0,0 +1,83 @@
+//! Checksummed config records
+//!
+//! A record wraps an encoded config with the magic bytes `NR`, a sequence
+//! number and a trailing CRC-32 over everything before it, so a partially
+//! written record is detected instead of being decoded.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+const MAGIC: [u8; 2] = *b"NR";
+const SEQUENCE_LEN: usize = 4;
+const CRC_LEN: usize = 4;
+
+pub struct Record<'a> {
+    pub sequence: u32,
+    pub payload: &'a [u8],
+}
+
+pub fn encode(sequence: u32, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAGIC.len() + SEQUENCE_LEN + payload.len() + CRC_LEN);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&sequence.to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes.extend_from_slice(&CRC.checksum(&bytes).to_le_bytes());
+    bytes
+}
+
+/// Returns `false` for bytes that were never a record, such as a config
+/// written before records existed.
+pub fn is_record(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Returns the record in `bytes`, or `None` if it is truncated or fails its
+/// checksum.
+pub fn decode(bytes: &[u8]) -> Option<Record<'_>> {
+    if !is_record(bytes) || bytes.len() < MAGIC.len() + SEQUENCE_LEN + CRC_LEN {
+        return None;
+    }
+
+    let (body, crc) = bytes.split_at(bytes.len() - CRC_LEN);
+    if CRC.checksum(body).to_le_bytes() != crc {
+        return None;
+    }
+
+    let (sequence, payload) = body[MAGIC.len()..].split_at(SEQUENCE_LEN);
+    Some(Record {
+        sequence: u32::from_le_bytes(sequence.try_into().unwrap()),
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_a_record() {
+        let bytes = encode(7, &[1, 2, 3]);
+
+        let record = decode(&bytes).unwrap();
+
+        assert_eq!(record.sequence, 7);
+        assert_eq!(record.payload, [1, 2, 3]);
+    }
+
+    #[test]
+    fn it_rejects_a_flipped_bit() {
+        let mut bytes = encode(7, &[1, 2, 3]);
+        bytes[7] ^= 0x01;
+
+        assert!(decode(&bytes).is_none());
+    }
+
+    #[test]
+    fn it_rejects_a_truncated_record() {
+        let bytes = encode(7, &[1, 2, 3]);
+
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_none());
+        }
+    }
+}
@@ -0,0 +1,819 @@
+//! Stored config layout
+//!
+//! A stored config starts with a header of the magic bytes `NC` and a schema
+//! version, followed by the postcard encoding of the config as it looked at
+//! that version. Configs written before the header existed are bare postcard
+//! bytes of the version 1 layout.
+//!
+//! The stored layout differs from `InternalConfig` in that secret fields are
+//! sealed with a `SecretCipher` rather than kept in plain text.
+//!
+//! When the layout changes, bump `CURRENT_VERSION`, freeze the previous
+//! layout in its own module with a conversion to the new one, and add a step
+//! to `decode_payload`. Older configs are upgraded one version at a time,
+//! still sealed, and only the current layout opens the secrets.
+
+use log::error;
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{InternalConfig, StaticIp};
+use crate::auth::PasswordHash;
+use crate::secrets::{SecretCipher, SecretError};
+use crate::wifi::{ApMode, KnownNetwork, WifiAuth};
+
+const MAGIC: [u8; 2] = *b"NC";
+
+pub const CURRENT_VERSION: u8 = 8;
+
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("unsupported config version {0}")]
+    UnsupportedVersion(u8),
+    #[error("error decoding config")]
+    Decode(#[from] postcard::Error),
+    #[error("error encoding config")]
+    Encode(#[source] postcard::Error),
+    #[error("error sealing or opening secrets")]
+    Secret(#[from] SecretError),
+}
+
+mod v1 {
+    use serde::Deserialize;
+
+    use super::v2;
+    use crate::secrets::{SecretCipher, SecretError};
+
+    /// Layout stored before secrets were encrypted.
+    #[derive(Deserialize)]
+    pub struct Config {
+        wifi_ssid: String,
+        wifi_pass: String,
+        tz: String,
+        led_color: u32,
+        hours_24: bool,
+    }
+
+    impl Config {
+        pub fn upgrade(self, cipher: &dyn SecretCipher) -> Result<v2::Config, SecretError> {
+            Ok(v2::Config {
+                wifi_ssid: self.wifi_ssid,
+                wifi_pass: cipher.seal(self.wifi_pass.as_bytes())?,
+                tz: self.tz,
+                led_color: self.led_color,
+                hours_24: self.hours_24,
+            })
+        }
+    }
+}
+
+mod v2 {
+    use serde::Deserialize;
+
+    use super::v3;
+
+    /// Layout stored before additional networks could be saved.
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub(super) wifi_ssid: String,
+        pub(super) wifi_pass: Vec<u8>,
+        pub(super) tz: String,
+        pub(super) led_color: u32,
+        pub(super) hours_24: bool,
+    }
+
+    impl From<Config> for v3::Config {
+        fn from(item: Config) -> Self {
+            v3::Config {
+                wifi_ssid: item.wifi_ssid,
+                wifi_pass: item.wifi_pass,
+                tz: item.tz,
+                led_color: item.led_color,
+                hours_24: item.hours_24,
+                networks: Vec::new(),
+            }
+        }
+    }
+}
+
+mod v3 {
+    use serde::Deserialize;
+
+    use super::v4;
+    use crate::wifi::WifiAuth;
+
+    #[derive(Deserialize)]
+    pub struct Network {
+        pub(super) ssid: String,
+        pub(super) pass: Vec<u8>,
+        pub(super) priority: u8,
+    }
+
+    /// Layout stored before the WiFi authentication method was configurable.
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub(super) wifi_ssid: String,
+        pub(super) wifi_pass: Vec<u8>,
+        pub(super) tz: String,
+        pub(super) led_color: u32,
+        pub(super) hours_24: bool,
+        pub(super) networks: Vec<Network>,
+    }
+
+    impl From<Config> for v4::Config {
+        fn from(item: Config) -> Self {
+            let networks = item
+                .networks
+                .into_iter()
+                .map(|n| v4::Network {
+                    ssid: n.ssid,
+                    pass: n.pass,
+                    auth: WifiAuth::Auto,
+                    priority: n.priority,
+                })
+                .collect();
+
+            v4::Config {
+                wifi_ssid: item.wifi_ssid,
+                wifi_pass: item.wifi_pass,
+                wifi_auth: WifiAuth::Auto,
+                tz: item.tz,
+                led_color: item.led_color,
+                hours_24: item.hours_24,
+                networks,
+            }
+        }
+    }
+}
+
+mod v4 {
+    use serde::Deserialize;
+
+    use super::v5;
+    use crate::wifi::WifiAuth;
+
+    #[derive(Deserialize)]
+    pub struct Network {
+        pub(super) ssid: String,
+        pub(super) pass: Vec<u8>,
+        pub(super) auth: WifiAuth,
+        pub(super) priority: u8,
+    }
+
+    /// Layout stored before static IP settings existed.
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub(super) wifi_ssid: String,
+        pub(super) wifi_pass: Vec<u8>,
+        pub(super) wifi_auth: WifiAuth,
+        pub(super) tz: String,
+        pub(super) led_color: u32,
+        pub(super) hours_24: bool,
+        pub(super) networks: Vec<Network>,
+    }
+
+    impl From<Config> for v5::Config {
+        fn from(item: Config) -> Self {
+            v5::Config {
+                wifi_ssid: item.wifi_ssid,
+                wifi_pass: item.wifi_pass,
+                wifi_auth: item.wifi_auth,
+                tz: item.tz,
+                led_color: item.led_color,
+                hours_24: item.hours_24,
+                networks: item.networks,
+                static_ip: None,
+            }
+        }
+    }
+}
+
+mod v5 {
+    use serde::Deserialize;
+
+    use super::{v4, v6, StaticIp};
+    use crate::secrets::{SecretCipher, SecretError};
+    use crate::wifi::{ApMode, WifiAuth};
+
+    /// Layout stored before the access point settings existed. Networks are
+    /// stored the same as in version 4.
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub(super) wifi_ssid: String,
+        pub(super) wifi_pass: Vec<u8>,
+        pub(super) wifi_auth: WifiAuth,
+        pub(super) tz: String,
+        pub(super) led_color: u32,
+        pub(super) hours_24: bool,
+        pub(super) networks: Vec<v4::Network>,
+        pub(super) static_ip: Option<StaticIp>,
+    }
+
+    impl Config {
+        pub fn upgrade(self, cipher: &dyn SecretCipher) -> Result<v6::Config, SecretError> {
+            Ok(v6::Config {
+                wifi_ssid: self.wifi_ssid,
+                wifi_pass: self.wifi_pass,
+                wifi_auth: self.wifi_auth,
+                tz: self.tz,
+                led_color: self.led_color,
+                hours_24: self.hours_24,
+                networks: self.networks,
+                static_ip: self.static_ip,
+                ap_mode: ApMode::default(),
+                ap_pass: cipher.seal(b"")?,
+            })
+        }
+    }
+}
+
+mod v6 {
+    use serde::Deserialize;
+
+    use super::{v4, v7, StaticIp};
+    use crate::wifi::{ApMode, WifiAuth};
+
+    /// Layout stored before the hostname was configurable.
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub(super) wifi_ssid: String,
+        pub(super) wifi_pass: Vec<u8>,
+        pub(super) wifi_auth: WifiAuth,
+        pub(super) tz: String,
+        pub(super) led_color: u32,
+        pub(super) hours_24: bool,
+        pub(super) networks: Vec<v4::Network>,
+        pub(super) static_ip: Option<StaticIp>,
+        pub(super) ap_mode: ApMode,
+        pub(super) ap_pass: Vec<u8>,
+    }
+
+    impl From<Config> for v7::Config {
+        fn from(item: Config) -> Self {
+            v7::Config {
+                wifi_ssid: item.wifi_ssid,
+                wifi_pass: item.wifi_pass,
+                wifi_auth: item.wifi_auth,
+                tz: item.tz,
+                led_color: item.led_color,
+                hours_24: item.hours_24,
+                networks: item.networks,
+                static_ip: item.static_ip,
+                ap_mode: item.ap_mode,
+                ap_pass: item.ap_pass,
+                hostname: String::new(),
+            }
+        }
+    }
+}
+
+mod v7 {
+    use serde::Deserialize;
+
+    use super::{v4, StaticIp, StoredConfig, StoredNetwork};
+    use crate::wifi::{ApMode, WifiAuth};
+
+    /// Layout stored before the admin password existed.
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub(super) wifi_ssid: String,
+        pub(super) wifi_pass: Vec<u8>,
+        pub(super) wifi_auth: WifiAuth,
+        pub(super) tz: String,
+        pub(super) led_color: u32,
+        pub(super) hours_24: bool,
+        pub(super) networks: Vec<v4::Network>,
+        pub(super) static_ip: Option<StaticIp>,
+        pub(super) ap_mode: ApMode,
+        pub(super) ap_pass: Vec<u8>,
+        pub(super) hostname: String,
+    }
+
+    impl From<Config> for StoredConfig {
+        fn from(item: Config) -> Self {
+            let networks = item
+                .networks
+                .into_iter()
+                .map(|n| StoredNetwork {
+                    ssid: n.ssid,
+                    pass: n.pass,
+                    auth: n.auth,
+                    priority: n.priority,
+                })
+                .collect();
+
+            StoredConfig {
+                wifi_ssid: item.wifi_ssid,
+                wifi_pass: item.wifi_pass,
+                wifi_auth: item.wifi_auth,
+                tz: item.tz,
+                led_color: item.led_color,
+                hours_24: item.hours_24,
+                networks,
+                static_ip: item.static_ip,
+                ap_mode: item.ap_mode,
+                ap_pass: item.ap_pass,
+                hostname: item.hostname,
+                admin_pass: None,
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredNetwork {
+    ssid: String,
+    pass: Vec<u8>,
+    auth: WifiAuth,
+    priority: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredConfig {
+    wifi_ssid: String,
+    wifi_pass: Vec<u8>,
+    wifi_auth: WifiAuth,
+    tz: String,
+    led_color: u32,
+    hours_24: bool,
+    networks: Vec<StoredNetwork>,
+    static_ip: Option<StaticIp>,
+    ap_mode: ApMode,
+    ap_pass: Vec<u8>,
+    hostname: String,
+    admin_pass: Option<PasswordHash>,
+}
+
+impl StoredConfig {
+    fn seal(config: &InternalConfig, cipher: &dyn SecretCipher) -> Result<Self, SecretError> {
+        let networks = config
+            .networks
+            .iter()
+            .map(|n| {
+                Ok(StoredNetwork {
+                    ssid: n.ssid.clone(),
+                    pass: cipher.seal(n.pass.as_bytes())?,
+                    auth: n.auth,
+                    priority: n.priority,
+                })
+            })
+            .collect::<Result<_, SecretError>>()?;
+
+        Ok(StoredConfig {
+            wifi_ssid: config.wifi_ssid.clone(),
+            wifi_pass: cipher.seal(config.wifi_pass.as_bytes())?,
+            wifi_auth: config.wifi_auth,
+            tz: config.tz.clone(),
+            led_color: config.led_color,
+            hours_24: config.hours_24,
+            networks,
+            static_ip: config.static_ip,
+            ap_mode: config.ap_mode,
+            ap_pass: cipher.seal(config.ap_pass.as_bytes())?,
+            hostname: config.hostname.clone(),
+            admin_pass: config.admin_pass.clone(),
+        })
+    }
+
+    fn open(self, cipher: &dyn SecretCipher) -> Result<InternalConfig, SecretError> {
+        let networks = self
+            .networks
+            .into_iter()
+            .map(|n| {
+                Ok(KnownNetwork {
+                    pass: open_secret("network password", &n.pass, cipher)?,
+                    ssid: n.ssid,
+                    auth: n.auth,
+                    priority: n.priority,
+                })
+            })
+            .collect::<Result<_, SecretError>>()?;
+
+        Ok(InternalConfig {
+            wifi_ssid: self.wifi_ssid,
+            wifi_pass: open_secret("WiFi password", &self.wifi_pass, cipher)?,
+            wifi_auth: self.wifi_auth,
+            tz: self.tz,
+            led_color: self.led_color,
+            hours_24: self.hours_24,
+            networks,
+            static_ip: self.static_ip,
+            ap_mode: self.ap_mode,
+            ap_pass: open_secret("access point password", &self.ap_pass, cipher)?,
+            hostname: self.hostname,
+            admin_pass: self.admin_pass,
+        })
+    }
+}
+
+/// A secret that can't be decrypted, for example because the key changed,
+/// makes the whole config unreadable. Dropping only the secret would lose it
+/// for good on the next save.
+fn open_secret(
+    name: &str,
+    sealed: &[u8],
+    cipher: &dyn SecretCipher,
+) -> Result<String, SecretError> {
+    let secret = cipher
+        .open(sealed)
+        .and_then(|plain| String::from_utf8(plain).map_err(|_| SecretError::Decrypt));
+    if secret.is_err() {
+        error!("Stored {} could not be decrypted", name);
+    }
+    secret
+}
+
+pub fn encode(config: &InternalConfig, cipher: &dyn SecretCipher) -> Result<Vec<u8>, SchemaError> {
+    let stored = StoredConfig::seal(config, cipher)?;
+
+    let mut bytes = Vec::from(MAGIC);
+    bytes.push(CURRENT_VERSION);
+    bytes.extend_from_slice(&to_allocvec(&stored).map_err(SchemaError::Encode)?);
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8], cipher: &dyn SecretCipher) -> Result<InternalConfig, SchemaError> {
+    match bytes {
+        [m0, m1, version, payload @ ..] if [*m0, *m1] == MAGIC => {
+            decode_payload(*version, payload, cipher)
+        }
+        // A legacy config starts with the SSID length, which is never long
+        // enough to be mistaken for the magic.
+        _ => decode_payload(1, bytes, cipher),
+    }
+}
+
+fn decode_payload(
+    version: u8,
+    payload: &[u8],
+    cipher: &dyn SecretCipher,
+) -> Result<InternalConfig, SchemaError> {
+    // Each step upgrades the config from the step before, or reads it from
+    // the payload if that is the version it was stored at
+    let as_v1: Option<v1::Config> = read_at(1, version, payload)?;
+    let as_v2: Option<v2::Config> = match as_v1 {
+        Some(older) => Some(older.upgrade(cipher)?),
+        None => read_at(2, version, payload)?,
+    };
+    let as_v3: Option<v3::Config> = match as_v2 {
+        Some(older) => Some(older.into()),
+        None => read_at(3, version, payload)?,
+    };
+    let as_v4: Option<v4::Config> = match as_v3 {
+        Some(older) => Some(older.into()),
+        None => read_at(4, version, payload)?,
+    };
+    let as_v5: Option<v5::Config> = match as_v4 {
+        Some(older) => Some(older.into()),
+        None => read_at(5, version, payload)?,
+    };
+    let as_v6: Option<v6::Config> = match as_v5 {
+        Some(older) => Some(older.upgrade(cipher)?),
+        None => read_at(6, version, payload)?,
+    };
+    let as_v7: Option<v7::Config> = match as_v6 {
+        Some(older) => Some(older.into()),
+        None => read_at(7, version, payload)?,
+    };
+    let current: Option<StoredConfig> = match as_v7 {
+        Some(older) => Some(older.into()),
+        None => read_at(CURRENT_VERSION, version, payload)?,
+    };
+
+    match current {
+        Some(config) => Ok(config.open(cipher)?),
+        None => Err(SchemaError::UnsupportedVersion(version)),
+    }
+}
+
+/// The config in `payload` if it was stored at version `at`.
+fn read_at<'a, T: Deserialize<'a>>(
+    at: u8,
+    version: u8,
+    payload: &'a [u8],
+) -> Result<Option<T>, postcard::Error> {
+    (version == at).then(|| from_bytes(payload)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::secrets::{ChaChaCipher, StaticKey};
+
+    // Config written by firmware that stored bare postcard bytes:
+    // InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false)
+    const LEGACY_V1: &[u8] = &[
+        4, b's', b's', b'i', b'd', 4, b'p', b'a', b's', b's', 10, b'U', b'S', b'/', b'C', b'e',
+        b'n', b't', b'r', b'a', b'l', 0xd6, 0xe8, 0x48, 0,
+    ];
+
+    fn cipher(key: u8) -> ChaChaCipher {
+        ChaChaCipher::new(&StaticKey::new([key; 32])).unwrap()
+    }
+
+    #[test]
+    fn it_writes_a_versioned_header() {
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
+
+        let bytes = encode(&config, &cipher(1)).unwrap();
+
+        assert_eq!(bytes[..3], [b'N', b'C', CURRENT_VERSION]);
+    }
+
+    #[test]
+    fn it_round_trips_the_current_version() {
+        let cipher = cipher(1);
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, true);
+
+        assert_eq!(
+            decode(&encode(&config, &cipher).unwrap(), &cipher).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn it_encrypts_the_wifi_password() {
+        let config = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, true);
+
+        let bytes = encode(&config, &cipher(1)).unwrap();
+
+        assert!(!bytes.windows(8).any(|w| w == b"hunter22"));
+    }
+
+    #[test]
+    fn it_rejects_a_config_whose_secrets_cannot_be_decrypted() {
+        let config = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, true);
+
+        let bytes = encode(&config, &cipher(1)).unwrap();
+
+        assert!(matches!(
+            decode(&bytes, &cipher(2)),
+            Err(SchemaError::Secret(SecretError::Decrypt))
+        ));
+    }
+
+    #[test]
+    fn it_decodes_legacy_configs_without_a_header() {
+        let expected = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
+
+        assert_eq!(decode(LEGACY_V1, &cipher(1)).unwrap(), expected);
+    }
+
+    #[test]
+    fn it_decodes_version_1_configs() {
+        let expected = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, false);
+
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(1);
+        bytes.extend_from_slice(LEGACY_V1);
+
+        assert_eq!(decode(&bytes, &cipher(1)).unwrap(), expected);
+    }
+
+    #[test]
+    fn it_decodes_version_2_configs() {
+        let cipher = cipher(1);
+        let expected = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false);
+
+        // Version 2 stored the same fields as version 1 with the password sealed
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(2);
+        bytes.extend_from_slice(&[4, b's', b's', b'i', b'd']);
+        let sealed = cipher.seal(b"hunter22").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.extend_from_slice(&LEGACY_V1[10..]);
+
+        assert_eq!(decode(&bytes, &cipher).unwrap(), expected);
+    }
+
+    #[test]
+    fn it_encrypts_saved_network_passwords() {
+        let mut config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, true);
+        config.save_network(KnownNetwork::new("home", "hunter22", 1));
+
+        let bytes = encode(&config, &cipher(1)).unwrap();
+
+        assert!(!bytes.windows(8).any(|w| w == b"hunter22"));
+        assert_eq!(decode(&bytes, &cipher(1)).unwrap(), config);
+    }
+
+    #[test]
+    fn it_decodes_version_3_configs() {
+        let cipher = cipher(1);
+        let expected = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false)
+            .with_networks(vec![KnownNetwork::new("home", "home-pass", 1)]);
+
+        // Version 3 added saved networks, without an authentication method
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(3);
+        bytes.extend_from_slice(&[4, b's', b's', b'i', b'd']);
+        let sealed = cipher.seal(b"hunter22").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.extend_from_slice(&LEGACY_V1[10..]);
+        bytes.extend_from_slice(&[1, 4, b'h', b'o', b'm', b'e']);
+        let sealed = cipher.seal(b"home-pass").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.push(1);
+
+        let config = decode(&bytes, &cipher).unwrap();
+
+        assert_eq!(config, expected);
+        assert_eq!(config.wifi_auth(), WifiAuth::Auto);
+    }
+
+    #[test]
+    fn it_round_trips_the_wifi_auth() {
+        let cipher = cipher(1);
+        let network = KnownNetwork::new("home", "hunter22", 1).with_auth(WifiAuth::Wpa2Personal);
+        let config = InternalConfig::new("ssid", "password", "US/Central", 0x123456, true)
+            .with_wifi_auth(WifiAuth::Wpa3Personal)
+            .with_networks(vec![network]);
+
+        assert_eq!(
+            decode(&encode(&config, &cipher).unwrap(), &cipher).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn it_decodes_version_4_configs() {
+        let cipher = cipher(1);
+        let expected = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false)
+            .with_wifi_auth(WifiAuth::Wpa2Personal);
+
+        // Version 4 added the authentication method, without static IP settings
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(4);
+        bytes.extend_from_slice(&[4, b's', b's', b'i', b'd']);
+        let sealed = cipher.seal(b"hunter22").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.push(2);
+        bytes.extend_from_slice(&LEGACY_V1[10..]);
+        bytes.push(0);
+
+        let config = decode(&bytes, &cipher).unwrap();
+
+        assert_eq!(config, expected);
+        assert_eq!(config.static_ip(), None);
+    }
+
+    #[test]
+    fn it_round_trips_static_ip_settings() {
+        let cipher = cipher(1);
+        let static_ip = StaticIp::new(
+            Ipv4Addr::new(10, 0, 0, 5),
+            Ipv4Addr::new(255, 255, 255, 0),
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 1),
+        );
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, true)
+            .with_static_ip(Some(static_ip));
+
+        assert_eq!(
+            decode(&encode(&config, &cipher).unwrap(), &cipher).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn it_decodes_version_5_configs() {
+        let cipher = cipher(1);
+        let expected = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false);
+
+        // Version 5 added static IP settings, without the access point ones
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(5);
+        bytes.extend_from_slice(&[4, b's', b's', b'i', b'd']);
+        let sealed = cipher.seal(b"hunter22").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.push(0);
+        bytes.extend_from_slice(&LEGACY_V1[10..]);
+        bytes.extend_from_slice(&[0, 0]);
+
+        let config = decode(&bytes, &cipher).unwrap();
+
+        assert_eq!(config, expected);
+        assert_eq!(config.ap_mode(), ApMode::WhenDisconnected);
+        assert_eq!(config.ap_pass(), "");
+    }
+
+    #[test]
+    fn it_encrypts_the_access_point_password() {
+        let cipher = cipher(1);
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, true)
+            .with_ap_mode(ApMode::Always)
+            .with_ap_pass("31415926");
+
+        let bytes = encode(&config, &cipher).unwrap();
+
+        assert!(!bytes.windows(8).any(|w| w == b"31415926"));
+        assert_eq!(decode(&bytes, &cipher).unwrap(), config);
+    }
+
+    #[test]
+    fn it_decodes_version_6_configs() {
+        let cipher = cipher(1);
+        let expected = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false)
+            .with_ap_mode(ApMode::Never)
+            .with_ap_pass("31415926");
+
+        // Version 6 added the access point settings, without a hostname
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(6);
+        bytes.extend_from_slice(&[4, b's', b's', b'i', b'd']);
+        let sealed = cipher.seal(b"hunter22").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.push(0);
+        bytes.extend_from_slice(&LEGACY_V1[10..]);
+        bytes.extend_from_slice(&[0, 0, 2]);
+        let sealed = cipher.seal(b"31415926").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+
+        let config = decode(&bytes, &cipher).unwrap();
+
+        assert_eq!(config, expected);
+        assert_eq!(config.hostname(), "");
+    }
+
+    #[test]
+    fn it_round_trips_the_hostname() {
+        let cipher = cipher(1);
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, true)
+            .with_hostname("kitchen");
+
+        assert_eq!(
+            decode(&encode(&config, &cipher).unwrap(), &cipher).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn it_decodes_version_7_configs() {
+        let cipher = cipher(1);
+        let expected = InternalConfig::new("ssid", "hunter22", "US/Central", 0x123456, false)
+            .with_ap_pass("31415926")
+            .with_hostname("kitchen");
+
+        // Version 7 added the hostname, without an admin password
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(7);
+        bytes.extend_from_slice(&[4, b's', b's', b'i', b'd']);
+        let sealed = cipher.seal(b"hunter22").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.push(0);
+        bytes.extend_from_slice(&LEGACY_V1[10..]);
+        bytes.extend_from_slice(&[0, 0, 1]);
+        let sealed = cipher.seal(b"31415926").unwrap();
+        bytes.push(sealed.len() as u8);
+        bytes.extend_from_slice(&sealed);
+        bytes.extend_from_slice(b"\x07kitchen");
+
+        let config = decode(&bytes, &cipher).unwrap();
+
+        assert_eq!(config, expected);
+        assert_eq!(config.admin_pass(), None);
+    }
+
+    #[test]
+    fn it_round_trips_the_admin_password() {
+        let cipher = cipher(1);
+        let admin_pass = PasswordHash::new("correct horse", 10).unwrap();
+        let config = InternalConfig::new("ssid", "pass", "US/Central", 0x123456, true)
+            .with_admin_pass(Some(admin_pass));
+
+        let config = decode(&encode(&config, &cipher).unwrap(), &cipher).unwrap();
+
+        assert!(config.admin_pass().unwrap().verify("correct horse"));
+    }
+
+    #[test]
+    fn it_rejects_unknown_versions() {
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(99);
+        bytes.extend_from_slice(LEGACY_V1);
+
+        assert!(matches!(
+            decode(&bytes, &cipher(1)),
+            Err(SchemaError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn it_rejects_truncated_configs() {
+        assert!(matches!(
+            decode(&LEGACY_V1[..8], &cipher(1)),
+            Err(SchemaError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+//! Static IPv4 settings
+//!
+//! Networks without DHCP need the address, netmask, gateway and DNS server
+//! set by hand. The web form carries them as dotted-quad strings so each one
+//! can be reported separately when it is malformed.
+
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StaticIp {
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Ipv4Addr,
+    dns: Ipv4Addr,
+}
+
+impl StaticIp {
+    pub fn new(address: Ipv4Addr, netmask: Ipv4Addr, gateway: Ipv4Addr, dns: Ipv4Addr) -> Self {
+        StaticIp {
+            address,
+            netmask,
+            gateway,
+            dns,
+        }
+    }
+
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        self.netmask
+    }
+
+    /// Number of leading one bits in the netmask.
+    pub fn prefix_len(&self) -> u8 {
+        u32::from(self.netmask).leading_ones() as u8
+    }
+
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    pub fn dns(&self) -> Ipv4Addr {
+        self.dns
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Validate)]
+#[validate(schema(
+    function = "validate_subnet",
+    message = "addresses are not consistent with the netmask"
+))]
+pub struct StaticIpConfig {
+    #[validate(custom(function = "validate_host", message = "address is invalid"))]
+    address: String,
+    #[validate(custom(function = "validate_netmask", message = "netmask is invalid"))]
+    netmask: String,
+    #[validate(custom(function = "validate_host", message = "gateway is invalid"))]
+    gateway: String,
+    #[validate(custom(function = "validate_host", message = "DNS server is invalid"))]
+    dns: String,
+}
+
+impl StaticIpConfig {
+    pub fn new(address: &str, netmask: &str, gateway: &str, dns: &str) -> Self {
+        StaticIpConfig {
+            address: String::from(address),
+            netmask: String::from(netmask),
+            gateway: String::from(gateway),
+            dns: String::from(dns),
+        }
+    }
+}
+
+impl From<StaticIp> for StaticIpConfig {
+    fn from(item: StaticIp) -> Self {
+        StaticIpConfig {
+            address: item.address.to_string(),
+            netmask: item.netmask.to_string(),
+            gateway: item.gateway.to_string(),
+            dns: item.dns.to_string(),
+        }
+    }
+}
+
+impl From<StaticIpConfig> for StaticIp {
+    fn from(item: StaticIpConfig) -> Self {
+        StaticIp {
+            address: item.address.parse().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            netmask: item.netmask.parse().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            gateway: item.gateway.parse().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            dns: item.dns.parse().unwrap_or(Ipv4Addr::UNSPECIFIED),
+        }
+    }
+}
+
+/// Accepts a unicast address that a host on the network could have.
+fn validate_host(addr: &str) -> Result<(), ValidationError> {
+    let addr: Ipv4Addr = addr
+        .parse()
+        .map_err(|_| ValidationError::new("invalid_address"))?;
+
+    if addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast() || addr.is_loopback() {
+        return Err(ValidationError::new("invalid_address"));
+    }
+    Ok(())
+}
+
+/// Accepts a netmask of contiguous one bits that leaves room for at least
+/// two hosts.
+fn validate_netmask(netmask: &str) -> Result<(), ValidationError> {
+    let mask = u32::from(
+        netmask
+            .parse::<Ipv4Addr>()
+            .map_err(|_| ValidationError::new("invalid_netmask"))?,
+    );
+
+    if mask.leading_ones() + mask.trailing_zeros() != 32 || !(1..=30).contains(&mask.count_ones()) {
+        return Err(ValidationError::new("invalid_netmask"));
+    }
+    Ok(())
+}
+
+/// Checks that the address and gateway share a subnet and neither is its
+/// network or broadcast address. Only runs once every field has parsed.
+fn validate_subnet(config: &StaticIpConfig) -> Result<(), ValidationError> {
+    let ip = StaticIp::from(config.clone());
+    let mask = u32::from(ip.netmask);
+    let network = u32::from(ip.address) & mask;
+
+    let is_host = |addr: Ipv4Addr| {
+        let addr = u32::from(addr);
+        addr & mask == network && addr != network && addr != network | !mask
+    };
+
+    if !is_host(ip.address) {
+        return Err(ValidationError::new("address_not_a_host"));
+    }
+    if !is_host(ip.gateway) {
+        return Err(ValidationError::new("gateway_outside_subnet"));
+    }
+    if ip.gateway == ip.address {
+        return Err(ValidationError::new("gateway_is_address"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> StaticIpConfig {
+        StaticIpConfig::new("192.168.1.50", "255.255.255.0", "192.168.1.1", "1.1.1.1")
+    }
+
+    fn field_error(config: StaticIpConfig, field: &str) -> bool {
+        config
+            .validate()
+            .unwrap_err()
+            .field_errors()
+            .contains_key(field)
+    }
+
+    #[test]
+    fn valid_static_ip() {
+        assert!(valid().validate().is_ok());
+    }
+
+    #[test]
+    fn it_converts_to_addresses() {
+        let ip = StaticIp::from(valid());
+
+        assert_eq!(ip.address(), Ipv4Addr::new(192, 168, 1, 50));
+        assert_eq!(ip.gateway(), Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(ip.dns(), Ipv4Addr::new(1, 1, 1, 1));
+        assert_eq!(ip.prefix_len(), 24);
+        assert_eq!(StaticIpConfig::from(ip), valid());
+    }
+
+    #[test]
+    fn validate_addresses_are_well_formed() {
+        for bad in ["", "192.168.1", "192.168.1.256", "192.168.1.50/24", "host"] {
+            let config = StaticIpConfig {
+                address: bad.to_string(),
+                ..valid()
+            };
+            assert!(field_error(config, "address"), "{:?}", bad);
+
+            let config = StaticIpConfig {
+                gateway: bad.to_string(),
+                ..valid()
+            };
+            assert!(field_error(config, "gateway"), "{:?}", bad);
+
+            let config = StaticIpConfig {
+                dns: bad.to_string(),
+                ..valid()
+            };
+            assert!(field_error(config, "dns"), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn validate_addresses_are_unicast() {
+        for bad in ["0.0.0.0", "255.255.255.255", "224.0.0.1", "127.0.0.1"] {
+            let config = StaticIpConfig {
+                address: bad.to_string(),
+                ..valid()
+            };
+            assert!(field_error(config, "address"), "{:?}", bad);
+
+            let config = StaticIpConfig {
+                dns: bad.to_string(),
+                ..valid()
+            };
+            assert!(field_error(config, "dns"), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn validate_netmask_is_contiguous() {
+        for bad in [
+            "255.0.255.0",
+            "0.0.0.0",
+            "255.255.255.255",
+            "255.255.255.254",
+            "mask",
+        ] {
+            let config = StaticIpConfig {
+                netmask: bad.to_string(),
+                ..valid()
+            };
+            assert!(field_error(config, "netmask"), "{:?}", bad);
+        }
+
+        // A /30 is a valid netmask but leaves the gateway outside the subnet
+        let config = StaticIpConfig {
+            netmask: "255.255.255.252".to_string(),
+            ..valid()
+        };
+        assert!(field_error(config, "__all__"));
+    }
+
+    #[test]
+    fn validate_gateway_is_in_subnet() {
+        let config = StaticIpConfig {
+            gateway: "192.168.2.1".to_string(),
+            ..valid()
+        };
+        assert!(field_error(config, "__all__"));
+
+        let config = StaticIpConfig {
+            netmask: "255.255.0.0".to_string(),
+            gateway: "192.168.2.1".to_string(),
+            ..valid()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_gateway_is_not_the_address() {
+        let config = StaticIpConfig {
+            gateway: "192.168.1.50".to_string(),
+            ..valid()
+        };
+        assert!(field_error(config, "__all__"));
+    }
+
+    #[test]
+    fn validate_address_is_not_network_or_broadcast() {
+        let config = StaticIpConfig {
+            address: "192.168.1.0".to_string(),
+            ..valid()
+        };
+        assert!(field_error(config, "__all__"));
+
+        let config = StaticIpConfig {
+            address: "192.168.1.255".to_string(),
+            ..valid()
+        };
+        assert!(field_error(config, "__all__"));
+
+        let config = StaticIpConfig {
+            gateway: "192.168.1.255".to_string(),
+            ..valid()
+        };
+        assert!(field_error(config, "__all__"));
+    }
+}
@@ -0,0 +1,285 @@
+//! Captive Portal DNS
+//!
+//! While the setup access point is up, every name a client looks up should
+//! lead to the clock so phones open the config page on their own. This only
+//! understands what that takes: standard queries with a single question,
+//! answered with one A record, or no records for other types.
+
+use std::net::Ipv4Addr;
+
+use thiserror::Error;
+
+const HEADER_LEN: usize = 12;
+/// Pointer to the name of the question, which always follows the header.
+const NAME_POINTER: [u8; 2] = [0xc0, HEADER_LEN as u8];
+const ANSWER_LEN: usize = 16;
+const MAX_NAME_LEN: usize = 255;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const TTL_SECS: u32 = 60;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DnsError {
+    #[error("packet is truncated")]
+    Truncated,
+    #[error("packet is not a query")]
+    NotAQuery,
+    #[error("only standard queries with one question are supported")]
+    Unsupported,
+    #[error("name is malformed")]
+    BadName,
+    #[error("response needs {required} bytes")]
+    BufferTooSmall { required: usize },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Query {
+    pub id: u16,
+    /// The name asked about, as dotted labels.
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    /// Length of the header and question.
+    len: usize,
+}
+
+pub fn parse_query(packet: &[u8]) -> Result<Query, DnsError> {
+    if packet.len() < HEADER_LEN {
+        return Err(DnsError::Truncated);
+    }
+
+    let id = read_u16(packet, 0)?;
+    let flags = read_u16(packet, 2)?;
+    if flags & FLAG_RESPONSE != 0 {
+        return Err(DnsError::NotAQuery);
+    }
+    if flags & OPCODE_MASK != 0 || read_u16(packet, 4)? != 1 {
+        return Err(DnsError::Unsupported);
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = HEADER_LEN;
+    loop {
+        let len = *packet.get(pos).ok_or(DnsError::Truncated)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Queries never need compression, so anything but a plain label
+        // is rejected
+        if len > 63 {
+            return Err(DnsError::BadName);
+        }
+        let label = packet.get(pos..pos + len).ok_or(DnsError::Truncated)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len;
+        if pos - HEADER_LEN > MAX_NAME_LEN {
+            return Err(DnsError::BadName);
+        }
+    }
+
+    let qtype = read_u16(packet, pos)?;
+    let qclass = read_u16(packet, pos + 2)?;
+
+    Ok(Query {
+        id,
+        name: labels.join("."),
+        qtype,
+        qclass,
+        len: pos + 4,
+    })
+}
+
+/// Whether `peer` is on the network of `address` with `prefix_len` bits.
+/// Only clients of the access point are answered, not ones on the network
+/// the clock joined.
+pub fn on_subnet(peer: Ipv4Addr, address: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = u32::MAX
+        .checked_shl(32 - u32::from(prefix_len.min(32)))
+        .unwrap_or(0);
+    u32::from(peer) & mask == u32::from(address) & mask
+}
+
+/// Writes the answer to the query in `packet` into `buf`, pointing A
+/// lookups at `address`, and returns the length of the response.
+pub fn build_response(packet: &[u8], address: Ipv4Addr, buf: &mut [u8]) -> Result<usize, DnsError> {
+    let query = parse_query(packet)?;
+    let answers = if query.qtype == TYPE_A && query.qclass == CLASS_IN {
+        1
+    } else {
+        0
+    };
+
+    let len = query.len + answers * ANSWER_LEN;
+    if buf.len() < len {
+        return Err(DnsError::BufferTooSmall { required: len });
+    }
+
+    let flags =
+        FLAG_RESPONSE | FLAG_AUTHORITATIVE | (read_u16(packet, 2)? & FLAG_RECURSION_DESIRED);
+
+    buf[0..2].copy_from_slice(&query.id.to_be_bytes());
+    buf[2..4].copy_from_slice(&flags.to_be_bytes());
+    buf[4..6].copy_from_slice(&1u16.to_be_bytes());
+    buf[6..8].copy_from_slice(&(answers as u16).to_be_bytes());
+    buf[8..12].fill(0);
+    buf[HEADER_LEN..query.len].copy_from_slice(&packet[HEADER_LEN..query.len]);
+
+    if answers == 1 {
+        let answer = &mut buf[query.len..len];
+        answer[0..2].copy_from_slice(&NAME_POINTER);
+        answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&address.octets());
+    }
+
+    Ok(len)
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Result<u16, DnsError> {
+    packet
+        .get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(DnsError::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+
+    /// A query for `example.com` as sent by `dig`.
+    fn query(qtype: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(b"\x07example\x03com\x00");
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn it_parses_a_query() {
+        let query = parse_query(&query(TYPE_A)).unwrap();
+
+        assert_eq!(query.id, 0x1234);
+        assert_eq!(query.name, "example.com");
+        assert_eq!(query.qtype, TYPE_A);
+        assert_eq!(query.qclass, CLASS_IN);
+    }
+
+    #[test]
+    fn it_answers_a_lookups_with_the_access_point() {
+        let packet = query(TYPE_A);
+        let mut buf = [0; 512];
+
+        let len = build_response(&packet, AP, &mut buf).unwrap();
+        let response = &buf[..len];
+
+        assert_eq!(response[0..2], [0x12, 0x34]);
+        // Response, authoritative, recursion desired copied from the query
+        assert_eq!(response[2..4], [0x85, 0x00]);
+        assert_eq!(response[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response[12..packet.len()], packet[12..]);
+        assert_eq!(
+            response[packet.len()..],
+            [0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 71, 1]
+        );
+    }
+
+    #[test]
+    fn it_answers_other_types_without_records() {
+        let aaaa = 28;
+        let packet = query(aaaa);
+        let mut buf = [0; 512];
+
+        let len = build_response(&packet, AP, &mut buf).unwrap();
+
+        assert_eq!(len, packet.len());
+        assert_eq!(buf[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn it_ignores_extra_bytes_after_the_question() {
+        let mut packet = query(TYPE_A);
+        let question_end = packet.len();
+        // An EDNS record some resolvers add
+        packet[11] = 1;
+        packet.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let mut buf = [0; 512];
+
+        let len = build_response(&packet, AP, &mut buf).unwrap();
+
+        assert_eq!(len, question_end + ANSWER_LEN);
+        assert_eq!(buf[10..12], [0, 0]);
+    }
+
+    #[test]
+    fn it_rejects_truncated_packets() {
+        let packet = query(TYPE_A);
+
+        assert_eq!(parse_query(&packet[..8]), Err(DnsError::Truncated));
+        assert_eq!(parse_query(&packet[..16]), Err(DnsError::Truncated));
+        assert_eq!(
+            parse_query(&packet[..packet.len() - 1]),
+            Err(DnsError::Truncated)
+        );
+    }
+
+    #[test]
+    fn it_rejects_responses() {
+        let mut packet = query(TYPE_A);
+        packet[2] |= 0x80;
+
+        assert_eq!(parse_query(&packet), Err(DnsError::NotAQuery));
+    }
+
+    #[test]
+    fn it_rejects_unsupported_queries() {
+        let mut packet = query(TYPE_A);
+        packet[5] = 2;
+        assert_eq!(parse_query(&packet), Err(DnsError::Unsupported));
+
+        // Opcode 2, a server status request
+        let mut packet = query(TYPE_A);
+        packet[2] |= 0x10;
+        assert_eq!(parse_query(&packet), Err(DnsError::Unsupported));
+    }
+
+    #[test]
+    fn it_rejects_compressed_names() {
+        let mut packet = query(TYPE_A);
+        packet[12] = 0xc0;
+
+        assert_eq!(parse_query(&packet), Err(DnsError::BadName));
+    }
+
+    #[test]
+    fn it_rejects_a_small_buffer() {
+        let packet = query(TYPE_A);
+        let mut buf = [0; 16];
+
+        assert_eq!(
+            build_response(&packet, AP, &mut buf),
+            Err(DnsError::BufferTooSmall {
+                required: packet.len() + ANSWER_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn it_only_answers_clients_of_the_access_point() {
+        assert!(on_subnet(Ipv4Addr::new(192, 168, 71, 2), AP, 24));
+        assert!(!on_subnet(Ipv4Addr::new(192, 168, 1, 20), AP, 24));
+        assert!(!on_subnet(Ipv4Addr::new(192, 168, 71, 2), AP, 32));
+        assert!(on_subnet(Ipv4Addr::new(10, 0, 0, 1), AP, 0));
+    }
+}
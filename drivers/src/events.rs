@@ -0,0 +1,287 @@
+//! Live Events
+//!
+//! Changes to what the clock shows, sent to browsers as Server-Sent Events.
+//! The main loop publishes to an [`EventBus`], which hands each change to
+//! every subscriber. A subscriber that falls too far behind is dropped
+//! rather than holding up the clock, and its browser reconnects.
+
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::nixie_display::{DisplayMode, Frame};
+
+/// Browsers that can follow the clock at once.
+pub const MAX_SUBSCRIBERS: usize = 3;
+/// Events that can wait for a subscriber before it is dropped.
+pub const SUBSCRIBER_BACKLOG: usize = 16;
+/// Sent when nothing has happened for `KEEP_ALIVE_INTERVAL`, so closed
+/// connections are noticed.
+pub const KEEP_ALIVE: &str = ": keep-alive\n\n";
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClockEvent {
+    Display { frame: Frame, mode: DisplayMode },
+    Led { color: u32 },
+    ConfigChanged,
+}
+
+impl ClockEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClockEvent::Display { .. } => "display",
+            ClockEvent::Led { .. } => "led",
+            ClockEvent::ConfigChanged => "configChanged",
+        }
+    }
+
+    /// The event in `text/event-stream` format.
+    pub fn to_sse(&self) -> String {
+        let data = match self {
+            ClockEvent::Display { frame, mode } => json!({
+                "digits": frame.digits,
+                "separators": frame.separators,
+                "mode": mode,
+            }),
+            ClockEvent::Led { color } => json!({ "color": format!("#{:06x}", color) }),
+            ClockEvent::ConfigChanged => json!({}),
+        };
+        format!("event: {}\ndata: {}\n\n", self.name(), data)
+    }
+}
+
+/// Events waiting to be sent to one browser.
+pub struct Subscription {
+    events: Receiver<String>,
+    _open: Arc<()>,
+}
+
+impl Subscription {
+    /// Waits for the next event, already in `text/event-stream` format.
+    /// `Disconnected` means the subscriber was dropped for falling behind.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<String, RecvTimeoutError> {
+        self.events.recv_timeout(timeout)
+    }
+}
+
+pub struct EventBus {
+    inner: Mutex<Inner>,
+}
+
+struct Subscriber {
+    events: SyncSender<String>,
+    open: Weak<()>,
+}
+
+struct Inner {
+    subscribers: Vec<Subscriber>,
+    max_subscribers: usize,
+    backlog: usize,
+    // The latest state, sent to new subscribers and used to skip repeats
+    display: Option<ClockEvent>,
+    led: Option<ClockEvent>,
+}
+
+impl EventBus {
+    pub fn new(max_subscribers: usize, backlog: usize) -> Self {
+        EventBus {
+            inner: Mutex::new(Inner {
+                subscribers: Vec::new(),
+                max_subscribers,
+                backlog,
+                display: None,
+                led: None,
+            }),
+        }
+    }
+
+    /// Starts following the clock with the current display and LED, or
+    /// returns `None` when there are already as many subscribers as allowed.
+    pub fn subscribe(&self) -> Option<Subscription> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.drop_closed();
+        if inner.subscribers.len() >= inner.max_subscribers {
+            return None;
+        }
+
+        let (sender, events) = sync_channel(inner.backlog);
+        for event in [&inner.display, &inner.led].into_iter().flatten() {
+            let _ = sender.try_send(event.to_sse());
+        }
+        let open = Arc::new(());
+        inner.subscribers.push(Subscriber {
+            events: sender,
+            open: Arc::downgrade(&open),
+        });
+        Some(Subscription {
+            events,
+            _open: open,
+        })
+    }
+
+    /// Sends an event to every subscriber. The display and LED are only sent
+    /// when they change.
+    pub fn publish(&self, event: ClockEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        let latest = match event {
+            ClockEvent::Display { .. } => Some(&mut inner.display),
+            ClockEvent::Led { .. } => Some(&mut inner.led),
+            ClockEvent::ConfigChanged => None,
+        };
+        if let Some(latest) = latest {
+            if latest.as_ref() == Some(&event) {
+                return;
+            }
+            *latest = Some(event.clone());
+        }
+
+        let sse = event.to_sse();
+        inner
+            .subscribers
+            .retain(|subscriber| subscriber.events.try_send(sse.clone()).is_ok());
+    }
+
+    pub fn subscribers(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        inner.drop_closed();
+        inner.subscribers.len()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(MAX_SUBSCRIBERS, SUBSCRIBER_BACKLOG)
+    }
+}
+
+impl Inner {
+    /// Forgets subscribers whose browsers have gone away.
+    fn drop_closed(&mut self) {
+        self.subscribers
+            .retain(|subscriber| subscriber.open.strong_count() > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_WAIT: Duration = Duration::from_millis(0);
+
+    fn display(digits: &[u8]) -> ClockEvent {
+        ClockEvent::Display {
+            frame: Frame {
+                digits: digits.iter().copied().map(Some).collect(),
+                separators: [true, false],
+            },
+            mode: DisplayMode::Time,
+        }
+    }
+
+    #[test]
+    fn it_formats_events_for_event_streams() {
+        assert_eq!(
+            display(&[1, 2, 3, 4]).to_sse(),
+            "event: display\ndata: {\"digits\":[1,2,3,4],\"mode\":\"time\",\"separators\":[true,false]}\n\n"
+        );
+        assert_eq!(
+            ClockEvent::Led { color: 0x00ff80 }.to_sse(),
+            "event: led\ndata: {\"color\":\"#00ff80\"}\n\n"
+        );
+        assert_eq!(
+            ClockEvent::ConfigChanged.to_sse(),
+            "event: configChanged\ndata: {}\n\n"
+        );
+    }
+
+    #[test]
+    fn it_sends_events_to_every_subscriber() {
+        let bus = EventBus::default();
+        let first = bus.subscribe().unwrap();
+        let second = bus.subscribe().unwrap();
+
+        bus.publish(ClockEvent::ConfigChanged);
+
+        assert_eq!(
+            first.recv_timeout(NO_WAIT),
+            Ok(ClockEvent::ConfigChanged.to_sse())
+        );
+        assert_eq!(
+            second.recv_timeout(NO_WAIT),
+            Ok(ClockEvent::ConfigChanged.to_sse())
+        );
+    }
+
+    #[test]
+    fn it_only_sends_changes() {
+        let bus = EventBus::default();
+        let subscriber = bus.subscribe().unwrap();
+
+        bus.publish(display(&[1, 2, 3, 4]));
+        bus.publish(display(&[1, 2, 3, 4]));
+        bus.publish(display(&[1, 2, 3, 5]));
+
+        assert_eq!(
+            subscriber.recv_timeout(NO_WAIT),
+            Ok(display(&[1, 2, 3, 4]).to_sse())
+        );
+        assert_eq!(
+            subscriber.recv_timeout(NO_WAIT),
+            Ok(display(&[1, 2, 3, 5]).to_sse())
+        );
+        assert!(subscriber.recv_timeout(NO_WAIT).is_err());
+    }
+
+    #[test]
+    fn it_starts_subscribers_with_the_current_state() {
+        let bus = EventBus::default();
+        bus.publish(display(&[1, 2, 3, 4]));
+        bus.publish(ClockEvent::Led { color: 0x123456 });
+
+        let subscriber = bus.subscribe().unwrap();
+
+        assert_eq!(
+            subscriber.recv_timeout(NO_WAIT),
+            Ok(display(&[1, 2, 3, 4]).to_sse())
+        );
+        assert_eq!(
+            subscriber.recv_timeout(NO_WAIT),
+            Ok(ClockEvent::Led { color: 0x123456 }.to_sse())
+        );
+    }
+
+    #[test]
+    fn it_limits_subscribers() {
+        let bus = EventBus::new(2, SUBSCRIBER_BACKLOG);
+        let first = bus.subscribe().unwrap();
+        let _second = bus.subscribe().unwrap();
+
+        assert!(bus.subscribe().is_none());
+
+        drop(first);
+        assert!(bus.subscribe().is_some());
+    }
+
+    #[test]
+    fn it_drops_subscribers_that_fall_behind() {
+        let bus = EventBus::new(MAX_SUBSCRIBERS, 2);
+        let slow = bus.subscribe().unwrap();
+        let fast = bus.subscribe().unwrap();
+
+        for digit in 0..3 {
+            bus.publish(display(&[digit]));
+            assert_eq!(fast.recv_timeout(NO_WAIT), Ok(display(&[digit]).to_sse()));
+        }
+
+        assert_eq!(bus.subscribers(), 1);
+        assert!(slow.recv_timeout(NO_WAIT).is_ok());
+        assert!(slow.recv_timeout(NO_WAIT).is_ok());
+        assert_eq!(
+            slow.recv_timeout(NO_WAIT),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Web Server Support
+//!
+//! Requests, responses and routing for the clock's web server, without
+//! depending on the HTTP server they run in. The server copies each request
+//! into a [`Request`], hands it to a [`Router`] and writes out the
+//! [`Response`], so handlers can be tested off the device.
+
+mod error;
+mod request;
+mod response;
+mod router;
+
+pub use error::{ApiError, ErrorCode};
+pub use request::{Method, Request};
+pub use response::Response;
+pub use router::Router;
+
+/// Largest request body accepted.
+pub const MAX_BODY_LEN: usize = 1024;
+/// Headers handlers look at. Servers that can't list every header of a
+/// request copy these.
+pub const REQUEST_HEADERS: [&str; 3] = ["Authorization", "Cookie", "Content-Type"];
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::auth::AuthError;
+use crate::config::{ImportError, PatchError};
+use crate::secrets::SecretError;
+use crate::storage::StorageError;
+
+/// What went wrong, so clients can react without parsing the message.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The body isn't JSON or doesn't have the expected shape.
+    BadRequest,
+    /// The body is well formed but some of its values aren't allowed.
+    Invalid,
+    Unauthorized,
+    /// No admin password has been set yet.
+    NoPassword,
+    NotFound,
+    MethodNotAllowed,
+    TooLarge,
+    LockedOut,
+    Unavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorCode::BadRequest | ErrorCode::Invalid => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::NoPassword => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::MethodNotAllowed => 405,
+            ErrorCode::TooLarge => 413,
+            ErrorCode::LockedOut => 429,
+            ErrorCode::Internal => 500,
+            ErrorCode::Unavailable => 503,
+        }
+    }
+}
+
+/// The body of every error response, such as
+/// `{"code":"invalid","message":"...","fields":{"ledColor":["..."]}}`.
+/// Fields are named as in the request, with nested ones joined by dots.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ApiError {
+    code: ErrorCode,
+    message: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<String, Vec<String>>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        ApiError {
+            code,
+            message: String::from(message),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_field(mut self, field: &str, message: &str) -> Self {
+        self.fields
+            .entry(String::from(field))
+            .or_default()
+            .push(String::from(message));
+        self
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn fields(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.fields
+    }
+
+    pub fn status(&self) -> u16 {
+        self.code.status()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("error responses always serialize")
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields = BTreeMap::new();
+        let mut general = Vec::new();
+        collect_fields(&errors, "", &mut fields, &mut general);
+        // Checks on the same field often fail together with the same message
+        for messages in fields.values_mut() {
+            messages.sort();
+            messages.dedup();
+        }
+
+        let message = if general.is_empty() {
+            String::from("some fields are invalid")
+        } else {
+            general.join("; ")
+        };
+        ApiError {
+            code: ErrorCode::Invalid,
+            message,
+            fields,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(error: serde_json::Error) -> Self {
+        ApiError::new(
+            ErrorCode::BadRequest,
+            &format!("invalid request body: {}", error),
+        )
+    }
+}
+
+impl From<PatchError> for ApiError {
+    fn from(error: PatchError) -> Self {
+        match error {
+            PatchError::Malformed(e) => e.into(),
+            PatchError::Invalid(e) => e.into(),
+        }
+    }
+}
+
+impl From<ImportError> for ApiError {
+    fn from(error: ImportError) -> Self {
+        let message = error.to_string();
+        match error {
+            ImportError::Invalid(e) => ApiError {
+                message,
+                ..e.into()
+            },
+            _ => ApiError::new(ErrorCode::BadRequest, &message),
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(error: AuthError) -> Self {
+        let code = match error {
+            AuthError::NoPassword => ErrorCode::NoPassword,
+            AuthError::Unauthorized => ErrorCode::Unauthorized,
+            AuthError::LockedOut(_) => ErrorCode::LockedOut,
+            AuthError::Session(_) => ErrorCode::Internal,
+        };
+        ApiError::new(code, &error.to_string())
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(error: StorageError) -> Self {
+        ApiError::new(ErrorCode::Internal, &error.to_string())
+    }
+}
+
+impl From<SecretError> for ApiError {
+    fn from(error: SecretError) -> Self {
+        ApiError::new(ErrorCode::Internal, &error.to_string())
+    }
+}
+
+/// Flattens nested validation errors into `fields`, keyed by their path.
+/// Errors about the struct as a whole go into `general`.
+fn collect_fields(
+    errors: &ValidationErrors,
+    prefix: &str,
+    fields: &mut BTreeMap<String, Vec<String>>,
+    general: &mut Vec<String>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = if field == "__all__" {
+            prefix.trim_end_matches('.').to_string()
+        } else {
+            format!("{}{}", prefix, json_name(field))
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                let messages = errors
+                    .iter()
+                    .map(|e| e.message.as_ref().unwrap_or(&e.code).to_string());
+                if path.is_empty() {
+                    general.extend(messages);
+                } else {
+                    fields.entry(path).or_default().extend(messages);
+                }
+            }
+            ValidationErrorsKind::Struct(errors) => {
+                collect_fields(errors, &format!("{}.", path), fields, general);
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_fields(errors, &format!("{}[{}].", path, index), fields, general);
+                }
+            }
+        }
+    }
+}
+
+/// The JSON name of a field: `wifi_ssid` is sent as `wifiSsid`. Underscores
+/// before digits stay, as in `hours_24`.
+fn json_name(field: &str) -> String {
+    let mut name = String::with_capacity(field.len());
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '_' && next.is_ascii_lowercase() => {
+                name.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => name.push(c),
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::{Config, ConfigPatch, InternalConfig, StaticIpConfig};
+    use crate::wifi::{KnownNetwork, WifiAuth};
+
+    #[test]
+    fn it_names_fields_as_they_are_sent() {
+        assert_eq!(json_name("wifi_ssid"), "wifiSsid");
+        assert_eq!(json_name("static_ip"), "staticIp");
+        assert_eq!(json_name("hours_24"), "hours_24");
+        assert_eq!(json_name("ssid"), "ssid");
+    }
+
+    #[test]
+    fn it_reports_invalid_fields() {
+        let config = Config::new("", "", "US/Central", "red", false);
+
+        let error = ApiError::from(config.validate().unwrap_err());
+
+        assert_eq!(error.code(), ErrorCode::Invalid);
+        assert_eq!(error.status(), 400);
+        assert_eq!(error.message(), "some fields are invalid");
+        assert_eq!(
+            error.fields().get("wifiSsid"),
+            Some(&vec![String::from("SSID must not be blank")])
+        );
+        assert_eq!(
+            error.fields().get("ledColor"),
+            Some(&vec![String::from("led color is invalid")])
+        );
+        assert_eq!(error.fields().len(), 2);
+    }
+
+    #[test]
+    fn it_reports_nested_fields_by_path() {
+        let config = Config::new("home", "hunter22", "US/Central", "#ff0000", false)
+            .with_static_ip(Some(StaticIpConfig::new("10.0.0.5", "24", "", "")));
+
+        let error = ApiError::from(config.validate().unwrap_err());
+
+        assert!(error.fields().contains_key("staticIp.netmask"));
+        assert!(error.fields().keys().all(|k| k.starts_with("staticIp.")));
+    }
+
+    #[test]
+    fn it_reports_struct_errors_in_the_message() {
+        let network = KnownNetwork::new("home", "", 1).with_auth(WifiAuth::Wpa2Personal);
+
+        let error = ApiError::from(network.validate().unwrap_err());
+
+        assert_eq!(
+            error.message(),
+            "password does not suit the authentication method"
+        );
+        assert!(error.fields().is_empty());
+    }
+
+    #[test]
+    fn it_reports_malformed_bodies() {
+        let error = ApiError::from(
+            ConfigPatch::apply(br#"{"brightness":1}"#, &InternalConfig::default()).unwrap_err(),
+        );
+
+        assert_eq!(error.code(), ErrorCode::BadRequest);
+        assert!(error.message().contains("brightness"));
+    }
+
+    #[test]
+    fn it_maps_auth_errors() {
+        let status = |e: AuthError| ApiError::from(e).status();
+
+        assert_eq!(status(AuthError::NoPassword), 403);
+        assert_eq!(status(AuthError::Unauthorized), 401);
+        assert_eq!(status(AuthError::LockedOut(Duration::from_secs(30))), 429);
+        assert_eq!(status(AuthError::Session(SecretError::Random)), 500);
+    }
+
+    #[test]
+    fn it_keeps_the_import_message_for_invalid_backups() {
+        let errors = Config::new("", "", "UTC", "#000000", false)
+            .validate()
+            .unwrap_err();
+
+        let error = ApiError::from(ImportError::Invalid(errors));
+
+        assert_eq!(error.message(), "backup contains an invalid config");
+        assert!(error.fields().contains_key("wifiSsid"));
+        assert_eq!(
+            ApiError::from(ImportError::ChecksumMismatch).code(),
+            ErrorCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn it_serializes_to_json() {
+        let error = ApiError::new(ErrorCode::NotFound, "no saved network named home");
+        assert_eq!(
+            error.to_json(),
+            r#"{"code":"not_found","message":"no saved network named home"}"#
+        );
+
+        let error = ApiError::new(ErrorCode::Invalid, "some fields are invalid")
+            .with_field("ledColor", "led color is invalid");
+        assert_eq!(
+            error.to_json(),
+            r#"{"code":"invalid","message":"some fields are invalid","fields":{"ledColor":["led color is invalid"]}}"#
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use serde::de::DeserializeOwned;
+
+use super::ApiError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+/// An HTTP request, copied out of whichever server received it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    method: Method,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Creates a request for `uri`, a path with an optional query string.
+    pub fn new(method: Method, uri: &str) -> Self {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+        Request {
+            method,
+            path: String::from(path),
+            query: String::from(query),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns a query parameter as it was sent, without decoding it. A
+    /// parameter without a value is the empty string.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns a header, ignoring the case of its name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Parses the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::http::ErrorCode;
+
+    #[test]
+    fn it_splits_the_query_from_the_path() {
+        let req = Request::new(Method::Get, "/config/export?secrets=false&pretty");
+
+        assert_eq!(req.path(), "/config/export");
+        assert_eq!(req.query("secrets"), Some("false"));
+        assert_eq!(req.query("pretty"), Some(""));
+        assert_eq!(req.query("other"), None);
+        assert_eq!(Request::new(Method::Get, "/").query("secrets"), None);
+    }
+
+    #[test]
+    fn it_finds_headers_in_any_case() {
+        let req = Request::new(Method::Get, "/").with_header("Content-Type", "application/json");
+
+        assert_eq!(req.header("content-type"), Some("application/json"));
+        assert_eq!(req.header("Cookie"), None);
+    }
+
+    #[test]
+    fn it_parses_json_bodies() {
+        #[derive(Deserialize)]
+        struct Form {
+            ssid: String,
+        }
+
+        let req = Request::new(Method::Post, "/").with_body(br#"{"ssid":"home"}"#);
+        assert_eq!(req.json::<Form>().unwrap().ssid, "home");
+
+        let req = Request::new(Method::Post, "/").with_body(b"ssid=home");
+        assert_eq!(
+            req.json::<Form>().err().map(|e| e.code()),
+            Some(ErrorCode::BadRequest)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use serde::Serialize;
+
+use super::{ApiError, ErrorCode};
+
+/// The body most changes answer with.
+const OK_BODY: &str = "{\"status\":\"ok\"}";
+
+/// An HTTP response, for whichever server sends it to write out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A 200 response with `{"status":"ok"}`, for requests that change
+    /// something.
+    pub fn ok() -> Self {
+        Response::new(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(OK_BODY.as_bytes())
+    }
+
+    pub fn json<T: Serialize>(value: &T) -> Result<Self, ApiError> {
+        let body = serde_json::to_vec(value)
+            .map_err(|e| ApiError::new(ErrorCode::Internal, &e.to_string()))?;
+        Ok(Response::new(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(&body))
+    }
+
+    pub fn html(html: &str) -> Self {
+        Response::new(200)
+            .with_header("Content-Type", "text/html")
+            .with_body(html.as_bytes())
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Response::new(302).with_header("Location", location)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Returns a header, ignoring the case of its name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+impl From<ApiError> for Response {
+    fn from(error: ApiError) -> Self {
+        Response::new(error.status())
+            .with_header("Content-Type", "application/json")
+            .with_body(error.to_json().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_answers_changes_with_ok() {
+        let response = Response::ok();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.body(), br#"{"status":"ok"}"#);
+    }
+
+    #[test]
+    fn it_serializes_json_bodies() {
+        let response = Response::json(&vec!["home", "office"]).unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), br#"["home","office"]"#);
+    }
+
+    #[test]
+    fn it_sends_errors_with_their_status() {
+        let response = Response::from(ApiError::new(ErrorCode::NotFound, "no such network"));
+
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(
+            response.body(),
+            br#"{"code":"not_found","message":"no such network"}"#
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use super::{ApiError, ErrorCode, Method, Request, Response};
+
+type Handler<S> = Box<dyn Fn(&Request, &S) -> Result<Response, ApiError> + Send + Sync>;
+
+struct Route<S> {
+    method: Method,
+    path: String,
+    handler: Handler<S>,
+}
+
+/// Sends requests to handlers by method and exact path. Handlers get the
+/// request and the state shared by all of them, and their errors become
+/// error responses.
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+}
+
+impl<S> Router<S> {
+    pub fn new() -> Self {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn route<F>(mut self, method: Method, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &S) -> Result<Response, ApiError> + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            path: String::from(path),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Lists the routes, for servers that need each one registered.
+    pub fn routes(&self) -> impl Iterator<Item = (Method, &str)> {
+        self.routes
+            .iter()
+            .map(|route| (route.method, route.path.as_str()))
+    }
+
+    pub fn handle(&self, req: &Request, state: &S) -> Response {
+        let mut path_found = false;
+        for route in self.routes.iter().filter(|r| r.path == req.path()) {
+            if route.method == req.method() {
+                return (route.handler)(req, state).unwrap_or_else(Response::from);
+            }
+            path_found = true;
+        }
+
+        if path_found {
+            ApiError::new(ErrorCode::MethodNotAllowed, "method not allowed").into()
+        } else {
+            ApiError::new(ErrorCode::NotFound, "not found").into()
+        }
+    }
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn router() -> Router<AtomicU32> {
+        Router::new()
+            .route(Method::Get, "/count", |_, count: &AtomicU32| {
+                Response::json(&count.load(Ordering::Relaxed))
+            })
+            .route(Method::Post, "/count", |_, count: &AtomicU32| {
+                count.fetch_add(1, Ordering::Relaxed);
+                Ok(Response::ok())
+            })
+            .route(Method::Delete, "/count", |_, _: &AtomicU32| {
+                Err(ApiError::new(ErrorCode::Unauthorized, "not yours"))
+            })
+    }
+
+    #[test]
+    fn it_routes_by_method_and_path() {
+        let router = router();
+        let count = AtomicU32::new(0);
+
+        let response = router.handle(&Request::new(Method::Post, "/count"), &count);
+        assert_eq!(response.status(), 200);
+
+        let response = router.handle(&Request::new(Method::Get, "/count?fresh"), &count);
+        assert_eq!(response.body(), b"1");
+    }
+
+    #[test]
+    fn it_turns_handler_errors_into_responses() {
+        let response = router().handle(&Request::new(Method::Delete, "/count"), &AtomicU32::new(0));
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.body(),
+            br#"{"code":"unauthorized","message":"not yours"}"#
+        );
+    }
+
+    #[test]
+    fn it_answers_unknown_routes() {
+        let router = router();
+        let count = AtomicU32::new(0);
+
+        let response = router.handle(&Request::new(Method::Get, "/counts"), &count);
+        assert_eq!(response.status(), 404);
+
+        let response = router.handle(&Request::new(Method::Put, "/count"), &count);
+        assert_eq!(response.status(), 405);
+    }
+
+    #[test]
+    fn it_lists_its_routes() {
+        let routes: Vec<_> = router().routes().map(|(m, p)| (m, p.to_string())).collect();
+
+        assert_eq!(
+            routes,
+            vec![
+                (Method::Get, String::from("/count")),
+                (Method::Post, String::from("/count")),
+                (Method::Delete, String::from("/count")),
+            ]
+        );
+    }
+}
@@ -1,8 +1,17 @@
 extern crate embedded_hal as hal;
 
+pub mod api;
+pub mod auth;
 pub mod config;
 pub mod debouncer;
+pub mod dns;
+pub mod events;
+pub mod http;
+pub mod long_press;
 pub mod nixie_display;
 pub mod rgb_led;
+pub mod secrets;
 pub mod shift_register;
+pub mod status;
 pub mod storage;
+pub mod wifi;
@@ -0,0 +1,106 @@
+//! Long Press Detection
+//!
+//! Tracks how long a button has been held so a destructive action, such as a
+//! factory reset, only fires after a deliberate hold and can show a countdown
+//! while it waits.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PressState {
+    Released,
+    /// Still held, with the whole seconds left before the press fires.
+    Holding {
+        remaining_secs: u32,
+    },
+    Triggered,
+}
+
+pub struct LongPress {
+    hold_time: Duration,
+    pressed_since: Option<Instant>,
+}
+
+impl LongPress {
+    pub fn new(hold_time: Duration) -> Self {
+        LongPress {
+            hold_time,
+            pressed_since: None,
+        }
+    }
+
+    pub fn update(&mut self, pressed: bool, now: Instant) -> PressState {
+        if !pressed {
+            self.pressed_since = None;
+            return PressState::Released;
+        }
+
+        let since = *self.pressed_since.get_or_insert(now);
+        let held = now.saturating_duration_since(since);
+
+        match self.hold_time.checked_sub(held) {
+            Some(remaining) if !remaining.is_zero() => PressState::Holding {
+                remaining_secs: remaining.as_millis().div_ceil(1000) as u32,
+            },
+            _ => PressState::Triggered,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_down_while_held() {
+        let start = Instant::now();
+        let mut press = LongPress::new(Duration::from_secs(3));
+
+        assert_eq!(
+            press.update(true, start),
+            PressState::Holding { remaining_secs: 3 }
+        );
+        assert_eq!(
+            press.update(true, start + Duration::from_millis(500)),
+            PressState::Holding { remaining_secs: 3 }
+        );
+        assert_eq!(
+            press.update(true, start + Duration::from_millis(1000)),
+            PressState::Holding { remaining_secs: 2 }
+        );
+        assert_eq!(
+            press.update(true, start + Duration::from_millis(2900)),
+            PressState::Holding { remaining_secs: 1 }
+        );
+    }
+
+    #[test]
+    fn it_triggers_after_hold_time() {
+        let start = Instant::now();
+        let mut press = LongPress::new(Duration::from_secs(3));
+
+        press.update(true, start);
+
+        assert_eq!(
+            press.update(true, start + Duration::from_secs(3)),
+            PressState::Triggered
+        );
+    }
+
+    #[test]
+    fn it_restarts_after_release() {
+        let start = Instant::now();
+        let mut press = LongPress::new(Duration::from_secs(3));
+
+        press.update(true, start);
+        assert_eq!(
+            press.update(false, start + Duration::from_secs(2)),
+            PressState::Released
+        );
+
+        assert_eq!(
+            press.update(true, start + Duration::from_secs(4)),
+            PressState::Holding { remaining_secs: 3 }
+        );
+    }
+}
@@ -1,7 +1,17 @@
 use crate::shift_register::Shift;
 use chrono::{Datelike, Timelike};
 use hal::digital::OutputPin;
+use serde::Serialize;
 
+mod overrides;
+
+pub use overrides::{DisplayOverride, OverrideStack, MAX_OVERRIDES};
+
+/// A digit the tube drivers decode to no digit at all.
+const BLANK: u8 = 0x0f;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
 pub enum DisplayMode {
     Time,
     Date,
@@ -19,6 +29,15 @@ pub struct NixieDisplay<'a, T, Pin1, Pin2> {
     seperator2: Pin2,
     mode: DisplayMode,
     hour_format: HourFormat,
+    frame: Frame,
+}
+
+/// What the tubes and separators last showed.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Frame {
+    /// `None` for a dark tube.
+    pub digits: Vec<Option<u8>>,
+    pub separators: [bool; 2],
 }
 
 impl<'a, T, Pin1, Pin2> NixieDisplay<'a, T, Pin1, Pin2>
@@ -34,6 +53,7 @@ where
             seperator2,
             mode: DisplayMode::Time,
             hour_format: HourFormat::TwelveHour,
+            frame: Frame::default(),
         }
     }
 
@@ -41,6 +61,14 @@ where
         self.mode = mode;
     }
 
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
     pub fn set_hour_format(&mut self, hour_format: HourFormat) {
         self.hour_format = hour_format;
     }
@@ -74,13 +102,8 @@ where
             (minutes % 10) as u8,
         ];
         self.show_digits(&digits);
-        if (time.second() % 2) == 0 {
-            self.seperator1.set_high().unwrap();
-            self.seperator2.set_high().unwrap();
-        } else {
-            self.seperator1.set_low().unwrap();
-            self.seperator2.set_low().unwrap();
-        }
+        let on = (time.second() % 2) == 0;
+        self.set_separators(on, on);
     }
 
     pub fn display_date(&mut self, time: impl Datelike) {
@@ -93,8 +116,7 @@ where
             (day % 10) as u8,
         ];
         self.show_digits(&digits);
-        self.seperator1.set_low().unwrap();
-        self.seperator2.set_high().unwrap();
+        self.set_separators(false, true);
     }
 
     pub fn display_year(&mut self, time: impl Datelike) {
@@ -106,8 +128,7 @@ where
             (year % 10) as u8,
         ];
         self.show_digits(&digits);
-        self.seperator1.set_low().unwrap();
-        self.seperator2.set_low().unwrap();
+        self.set_separators(false, false);
     }
 
     pub fn show_digits(&mut self, digits: &[u8]) {
@@ -122,6 +143,25 @@ where
             self.shift_register.shift(a * 16 + b);
         }
         self.shift_register.store();
+        self.frame.digits = digits
+            .iter()
+            .map(|&digit| (digit != BLANK).then_some(digit))
+            .collect();
+    }
+
+    /// Shows an override in place of the current mode, which comes back with
+    /// the next call to `display`.
+    pub fn show_override(&mut self, display: &DisplayOverride) {
+        let digits = display.digits.map(|digit| digit.unwrap_or(BLANK));
+        self.show_digits(&digits);
+        let [first, second] = display.separators;
+        self.set_separators(first, second);
+    }
+
+    pub fn set_separators(&mut self, first: bool, second: bool) {
+        self.seperator1.set_state(first.into()).unwrap();
+        self.seperator2.set_state(second.into()).unwrap();
+        self.frame.separators = [first, second];
     }
 }
 
@@ -266,4 +306,52 @@ mod tests {
         assert_eq!(sep1.states()[0], PinState::Low);
         assert_eq!(sep2.states()[0], PinState::Low);
     }
+
+    #[test]
+    fn it_shows_overrides() {
+        let mut mock = MockShift::new();
+        let recorder = Recorder::new();
+        let mut sep1 = recorder.create_pin(0);
+        let mut sep2 = recorder.create_pin(1);
+
+        let mut display = NixieDisplay::new(&mut mock, &mut sep1, &mut sep2);
+
+        display.show_override(&DisplayOverride {
+            digits: [None, Some(4), Some(2), None],
+            separators: [true, false],
+            ..Default::default()
+        });
+
+        assert_eq!(display.frame().digits, vec![None, Some(4), Some(2), None]);
+        drop(display);
+        let value = &mock.values[0];
+        assert_eq!(value[0], BLANK * 16 + 2);
+        assert_eq!(value[1], 4 * 16 + BLANK);
+        assert_eq!(sep1.states()[0], PinState::High);
+        assert_eq!(sep2.states()[0], PinState::Low);
+    }
+
+    #[test]
+    fn it_remembers_what_it_showed() {
+        let mut mock = MockShift::new();
+        let recorder = Recorder::new();
+        let sep1 = recorder.create_pin(0);
+        let sep2 = recorder.create_pin(1);
+
+        let mut display = NixieDisplay::new(&mut mock, sep1, sep2);
+
+        let date = NaiveDate::from_ymd_opt(2024, 12, 23).unwrap();
+        let time = NaiveTime::from_hms_opt(12, 34, 0).unwrap();
+        display.set_mode(DisplayMode::Date);
+        display.display(NaiveDateTime::new(date, time));
+
+        assert_eq!(display.mode(), DisplayMode::Date);
+        assert_eq!(
+            display.frame(),
+            &Frame {
+                digits: vec![Some(1), Some(2), Some(2), Some(3)],
+                separators: [false, true],
+            }
+        );
+    }
 }
@@ -0,0 +1,176 @@
+//! Display Overrides
+//!
+//! Numbers pushed to the tubes from outside, like a doorbell code or a
+//! temperature. An override is shown instead of the current `DisplayMode`
+//! until it expires, and the clock goes back to that mode afterwards. The
+//! highest priority override is shown, the newest one if several share it.
+
+use std::time::{Duration, Instant};
+
+/// Overrides that can be waiting at once.
+pub const MAX_OVERRIDES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DisplayOverride {
+    /// `None` leaves a tube dark.
+    pub digits: [Option<u8>; 4],
+    pub separators: [bool; 2],
+    /// The LED color to show with it, instead of the configured one.
+    pub led_color: Option<u32>,
+    pub priority: u8,
+}
+
+struct Active {
+    display: DisplayOverride,
+    expires: Instant,
+}
+
+pub struct OverrideStack {
+    overrides: Vec<Active>,
+    max_overrides: usize,
+}
+
+impl OverrideStack {
+    pub fn new(max_overrides: usize) -> Self {
+        OverrideStack {
+            overrides: Vec::new(),
+            max_overrides,
+        }
+    }
+
+    /// Shows `display` for `duration`. When the stack is full it replaces
+    /// the lowest priority override, unless that one outranks it, in which
+    /// case nothing changes and `false` is returned.
+    pub fn push(&mut self, display: DisplayOverride, duration: Duration, now: Instant) -> bool {
+        self.expire(now);
+        if self.overrides.len() >= self.max_overrides {
+            let lowest = self
+                .overrides
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, active)| (active.display.priority, active.expires))
+                .map(|(i, _)| i);
+            match lowest {
+                Some(i) if self.overrides[i].display.priority <= display.priority => {
+                    self.overrides.remove(i);
+                }
+                _ => return false,
+            }
+        }
+
+        self.overrides.push(Active {
+            display,
+            expires: now + duration,
+        });
+        true
+    }
+
+    /// The override to show now, if any.
+    pub fn current(&mut self, now: Instant) -> Option<&DisplayOverride> {
+        self.expire(now);
+        // The last of the highest priority is the newest
+        self.overrides
+            .iter()
+            .max_by_key(|active| active.display.priority)
+            .map(|active| &active.display)
+    }
+
+    pub fn clear(&mut self) {
+        self.overrides.clear();
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.overrides.retain(|active| active.expires > now);
+    }
+}
+
+impl Default for OverrideStack {
+    fn default() -> Self {
+        OverrideStack::new(MAX_OVERRIDES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn number(digit: u8, priority: u8) -> DisplayOverride {
+        DisplayOverride {
+            digits: [None, None, None, Some(digit)],
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_shows_nothing_by_default() {
+        let mut stack = OverrideStack::default();
+
+        assert_eq!(stack.current(Instant::now()), None);
+    }
+
+    #[test]
+    fn it_shows_an_override_until_it_expires() {
+        let mut stack = OverrideStack::default();
+        let now = Instant::now();
+
+        assert!(stack.push(number(1, 0), 10 * SECOND, now));
+
+        assert_eq!(stack.current(now + 9 * SECOND), Some(&number(1, 0)));
+        assert_eq!(stack.current(now + 10 * SECOND), None);
+    }
+
+    #[test]
+    fn it_shows_the_highest_priority() {
+        let mut stack = OverrideStack::default();
+        let now = Instant::now();
+
+        stack.push(number(1, 5), 10 * SECOND, now);
+        stack.push(number(2, 0), 30 * SECOND, now);
+
+        assert_eq!(stack.current(now), Some(&number(1, 5)));
+        // The lower priority one comes back when the other expires
+        assert_eq!(stack.current(now + 20 * SECOND), Some(&number(2, 0)));
+    }
+
+    #[test]
+    fn it_shows_the_newest_of_equal_priority() {
+        let mut stack = OverrideStack::default();
+        let now = Instant::now();
+
+        stack.push(number(1, 0), 10 * SECOND, now);
+        stack.push(number(2, 0), 10 * SECOND, now + SECOND);
+
+        assert_eq!(stack.current(now + SECOND), Some(&number(2, 0)));
+    }
+
+    #[test]
+    fn it_replaces_the_lowest_priority_when_full() {
+        let mut stack = OverrideStack::new(2);
+        let now = Instant::now();
+        stack.push(number(1, 1), 10 * SECOND, now);
+        stack.push(number(2, 3), 10 * SECOND, now);
+        let shown = |stack: &OverrideStack| -> Vec<_> {
+            stack.overrides.iter().map(|a| a.display.clone()).collect()
+        };
+
+        assert!(!stack.push(number(3, 0), 10 * SECOND, now));
+        assert_eq!(shown(&stack), [number(1, 1), number(2, 3)]);
+
+        assert!(stack.push(number(4, 2), 10 * SECOND, now));
+        assert_eq!(shown(&stack), [number(2, 3), number(4, 2)]);
+    }
+
+    #[test]
+    fn it_clears_overrides() {
+        let mut stack = OverrideStack::default();
+        let now = Instant::now();
+        stack.push(number(1, 0), 10 * SECOND, now);
+
+        stack.clear();
+
+        assert_eq!(stack.current(now), None);
+    }
+}
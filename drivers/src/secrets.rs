@@ -0,0 +1,220 @@
+//! Secret Encryption
+//!
+//! Encrypts secret config fields with ChaCha20-Poly1305 before they reach
+//! storage. The key comes from a `KeySource`. On the device it is a random
+//! [`StoredKey`], and on a host usually a fixed key.
+//!
+//! Sealing only keeps secrets from a flash dump when the key itself is out of
+//! reach, which on the device takes NVS encryption. The firmware enables it,
+//! but a clock flashed without it keeps the key in flash beside the secrets
+//! it seals.
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use log::error;
+use thiserror::Error;
+
+use crate::storage::{Storage, StorageError};
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+/// Where a `StoredKey` is kept.
+const STORED_KEY: &str = "secrets_key";
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("encryption key is unavailable: {0}")]
+    KeyUnavailable(String),
+    #[error("error generating nonce")]
+    Nonce,
+    #[error("no random numbers available")]
+    Random,
+    #[error("error encrypting secret")]
+    Encrypt,
+    #[error("secret could not be decrypted")]
+    Decrypt,
+}
+
+pub trait KeySource {
+    fn key(&self) -> Result<[u8; 32], SecretError>;
+}
+
+/// A key provided up front, for hosts and tests.
+pub struct StaticKey([u8; 32]);
+
+impl StaticKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        StaticKey(key)
+    }
+}
+
+impl KeySource for StaticKey {
+    fn key(&self) -> Result<[u8; 32], SecretError> {
+        Ok(self.0)
+    }
+}
+
+/// A random key, generated once for each clock and kept in storage.
+pub struct StoredKey([u8; KEY_LEN]);
+
+impl StoredKey {
+    /// Reads the key, or returns `None` if none has been stored yet. A
+    /// corrupt key is reported and treated as missing, since nothing sealed
+    /// with it can be opened anyway.
+    pub fn load(storage: &dyn Storage) -> Result<Option<StoredKey>, SecretError> {
+        match storage.get_vec(STORED_KEY) {
+            Ok(raw) => match <[u8; KEY_LEN]>::try_from(raw.as_slice()) {
+                Ok(key) => Ok(Some(StoredKey(key))),
+                Err(_) => {
+                    error!("Stored secrets key is corrupt, a new one will be generated");
+                    Ok(None)
+                }
+            },
+            Err(StorageError::NotFound(_)) => Ok(None),
+            Err(e) => Err(SecretError::KeyUnavailable(e.to_string())),
+        }
+    }
+
+    pub fn generate() -> Result<StoredKey, SecretError> {
+        let mut key = [0; KEY_LEN];
+        getrandom::getrandom(&mut key).map_err(|_| SecretError::Random)?;
+        Ok(StoredKey(key))
+    }
+
+    pub fn store(&self, storage: &mut dyn Storage) -> Result<(), SecretError> {
+        storage
+            .set_raw(STORED_KEY, &self.0)
+            .map_err(|e| SecretError::KeyUnavailable(e.to_string()))?;
+        Ok(())
+    }
+}
+
+impl KeySource for StoredKey {
+    fn key(&self) -> Result<[u8; 32], SecretError> {
+        Ok(self.0)
+    }
+}
+
+pub trait SecretCipher {
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, SecretError>;
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, SecretError>;
+}
+
+/// Seals secrets as a random nonce followed by the ciphertext and tag.
+pub struct ChaChaCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl ChaChaCipher {
+    pub fn new(keys: &dyn KeySource) -> Result<Self, SecretError> {
+        let key = keys.key()?;
+        Ok(ChaChaCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        })
+    }
+}
+
+impl SecretCipher for ChaChaCipher {
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, SecretError> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|_| SecretError::Nonce)?;
+
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| SecretError::Encrypt)?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, SecretError> {
+        if sealed.len() < NONCE_LEN {
+            return Err(SecretError::Decrypt);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SecretError::Decrypt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryStorage;
+
+    fn cipher(key: u8) -> ChaChaCipher {
+        ChaChaCipher::new(&StaticKey::new([key; 32])).unwrap()
+    }
+
+    #[test]
+    fn it_round_trips_a_secret() {
+        let cipher = cipher(1);
+
+        let sealed = cipher.seal(b"hunter22").unwrap();
+
+        assert_eq!(cipher.open(&sealed).unwrap(), b"hunter22");
+    }
+
+    #[test]
+    fn it_does_not_store_plaintext() {
+        let sealed = cipher(1).seal(b"hunter22").unwrap();
+
+        assert!(!sealed.windows(8).any(|w| w == b"hunter22"));
+    }
+
+    #[test]
+    fn it_uses_a_fresh_nonce_for_each_seal() {
+        let cipher = cipher(1);
+
+        assert_ne!(
+            cipher.seal(b"hunter22").unwrap(),
+            cipher.seal(b"hunter22").unwrap()
+        );
+    }
+
+    #[test]
+    fn it_rejects_the_wrong_key() {
+        let sealed = cipher(1).seal(b"hunter22").unwrap();
+
+        assert!(matches!(cipher(2).open(&sealed), Err(SecretError::Decrypt)));
+    }
+
+    #[test]
+    fn it_rejects_tampered_secrets() {
+        let cipher = cipher(1);
+        let mut sealed = cipher.seal(b"hunter22").unwrap();
+        sealed[NONCE_LEN] ^= 0x01;
+
+        assert!(matches!(cipher.open(&sealed), Err(SecretError::Decrypt)));
+        assert!(matches!(cipher.open(&[0; 4]), Err(SecretError::Decrypt)));
+    }
+
+    #[test]
+    fn it_generates_and_keeps_a_key() {
+        let mut storage = InMemoryStorage::new();
+        assert!(StoredKey::load(&storage).unwrap().is_none());
+
+        let key = StoredKey::generate().unwrap();
+        key.store(&mut storage).unwrap();
+
+        let loaded = StoredKey::load(&storage).unwrap().unwrap();
+        assert_eq!(loaded.key().unwrap(), key.key().unwrap());
+        assert_ne!(
+            StoredKey::generate().unwrap().key().unwrap(),
+            key.key().unwrap()
+        );
+    }
+
+    #[test]
+    fn it_treats_a_corrupt_key_as_missing() {
+        let mut storage = InMemoryStorage::new();
+        storage.set_raw(STORED_KEY, &[1, 2, 3]).unwrap();
+
+        assert!(StoredKey::load(&storage).unwrap().is_none());
+    }
+}
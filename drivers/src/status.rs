@@ -0,0 +1,237 @@
+//! Device Status
+//!
+//! A snapshot of how the clock is doing, kept up to date by the main loop and
+//! served at `/status`. Tools read it, so the schema is stable: fields and
+//! enum variants are only ever added, and anything else bumps
+//! [`STATUS_SCHEMA_VERSION`].
+
+use std::net::Ipv4Addr;
+
+use chrono::{DateTime, FixedOffset, SecondsFormat, TimeZone, Utc};
+use serde::{Serialize, Serializer};
+
+pub const STATUS_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceStatus {
+    schema_version: u32,
+    pub time: TimeStatus,
+    pub wifi: NetworkStatus,
+    pub system: SystemStatus,
+    pub firmware: FirmwareInfo,
+}
+
+impl DeviceStatus {
+    pub fn new(
+        time: TimeStatus,
+        wifi: NetworkStatus,
+        system: SystemStatus,
+        firmware: FirmwareInfo,
+    ) -> Self {
+        DeviceStatus {
+            schema_version: STATUS_SCHEMA_VERSION,
+            time,
+            wifi,
+            system,
+            firmware,
+        }
+    }
+}
+
+impl Default for DeviceStatus {
+    fn default() -> Self {
+        DeviceStatus::new(
+            TimeStatus::default(),
+            NetworkStatus::default(),
+            SystemStatus::default(),
+            FirmwareInfo::default(),
+        )
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeStatus {
+    /// The time shown on the display, with the offset of its time zone.
+    #[serde(serialize_with = "rfc3339")]
+    pub local_time: DateTime<FixedOffset>,
+    pub time_zone: String,
+    pub sync: SyncState,
+    /// When SNTP last set the clock, if it has since starting.
+    #[serde(serialize_with = "optional_rfc3339")]
+    pub last_sync: Option<DateTime<Utc>>,
+}
+
+impl Default for TimeStatus {
+    fn default() -> Self {
+        TimeStatus {
+            local_time: DateTime::UNIX_EPOCH.fixed_offset(),
+            time_zone: String::from("UTC"),
+            sync: SyncState::default(),
+            last_sync: None,
+        }
+    }
+}
+
+/// Where SNTP is at with setting the clock.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncState {
+    #[default]
+    NotSynced,
+    InProgress,
+    Synced,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkStatus {
+    /// The network the clock is connected to, if any.
+    pub ssid: Option<String>,
+    pub rssi: Option<i8>,
+    pub ip: Option<Ipv4Addr>,
+    pub ap_enabled: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemStatus {
+    pub uptime_secs: u64,
+    pub free_heap: u32,
+    /// The least free heap there has been since starting.
+    pub min_free_heap: u32,
+    pub reset_reason: ResetReason,
+    /// Whether stored passwords are out of reach of a flash dump. They are
+    /// always sealed, but without NVS encryption the key sits beside them.
+    pub secrets_protected: bool,
+}
+
+/// Why the clock last started.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ResetReason {
+    #[default]
+    Unknown,
+    PowerOn,
+    External,
+    Software,
+    Panic,
+    InterruptWatchdog,
+    TaskWatchdog,
+    Watchdog,
+    DeepSleep,
+    Brownout,
+    Sdio,
+}
+
+/// The firmware that is running, as recorded when it was built.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareInfo {
+    pub version: String,
+    pub git_hash: String,
+}
+
+impl FirmwareInfo {
+    pub fn new(version: &str, git_hash: &str) -> Self {
+        FirmwareInfo {
+            version: String::from(version),
+            git_hash: String::from(git_hash),
+        }
+    }
+}
+
+fn rfc3339<Tz, S>(time: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    Tz: TimeZone,
+    Tz::Offset: std::fmt::Display,
+    S: Serializer,
+{
+    serializer.serialize_str(&time.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+fn optional_rfc3339<S: Serializer>(
+    time: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => rfc3339(time, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_serializes_the_whole_status() {
+        let offset = FixedOffset::west_opt(5 * 3600).unwrap();
+        let status = DeviceStatus::new(
+            TimeStatus {
+                local_time: offset.with_ymd_and_hms(2025, 3, 1, 8, 30, 0).unwrap(),
+                time_zone: String::from("US/Central"),
+                sync: SyncState::Synced,
+                last_sync: Some(Utc.with_ymd_and_hms(2025, 3, 1, 13, 0, 0).unwrap()),
+            },
+            NetworkStatus {
+                ssid: Some(String::from("home")),
+                rssi: Some(-52),
+                ip: Some(Ipv4Addr::new(192, 168, 1, 20)),
+                ap_enabled: false,
+            },
+            SystemStatus {
+                uptime_secs: 3600,
+                free_heap: 120_000,
+                min_free_heap: 90_000,
+                reset_reason: ResetReason::PowerOn,
+                secrets_protected: false,
+            },
+            FirmwareInfo::new("0.1.0", "1a2b3c4"),
+        );
+
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            serde_json::json!({
+                "schemaVersion": 1,
+                "time": {
+                    "localTime": "2025-03-01T08:30:00-05:00",
+                    "timeZone": "US/Central",
+                    "sync": "synced",
+                    "lastSync": "2025-03-01T13:00:00Z",
+                },
+                "wifi": {
+                    "ssid": "home",
+                    "rssi": -52,
+                    "ip": "192.168.1.20",
+                    "apEnabled": false,
+                },
+                "system": {
+                    "uptimeSecs": 3600,
+                    "freeHeap": 120000,
+                    "minFreeHeap": 90000,
+                    "resetReason": "powerOn",
+                    "secretsProtected": false,
+                },
+                "firmware": {
+                    "version": "0.1.0",
+                    "gitHash": "1a2b3c4",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn it_keeps_unknown_fields_as_null() {
+        let status = serde_json::to_value(DeviceStatus::default()).unwrap();
+
+        assert_eq!(status["schemaVersion"], STATUS_SCHEMA_VERSION);
+        assert_eq!(status["time"]["localTime"], "1970-01-01T00:00:00Z");
+        assert_eq!(status["time"]["sync"], "notSynced");
+        assert_eq!(status["time"]["lastSync"], serde_json::Value::Null);
+        assert_eq!(status["wifi"]["ssid"], serde_json::Value::Null);
+        assert_eq!(status["wifi"]["ip"], serde_json::Value::Null);
+        assert_eq!(status["system"]["resetReason"], "unknown");
+    }
+}
@@ -1,17 +1,50 @@
 use std::collections::HashMap;
 use thiserror::Error;
 
+#[cfg(feature = "std")]
+mod file;
+
+#[cfg(feature = "std")]
+pub use file::FileStorage;
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
 #[derive(Error, Debug)]
 pub enum StorageError {
+    #[error("no value stored under `{0}`")]
+    NotFound(String),
+    #[error("buffer too small, {required} bytes required")]
+    BufferTooSmall { required: usize },
+    #[error("stored data is corrupt")]
+    Corrupt,
     #[error("error reading from storage")]
-    ReadError,
+    ReadError(#[source] BackendError),
     #[error("error writing to storage")]
-    WriteError,
+    WriteError(#[source] BackendError),
 }
 
 pub trait Storage {
     fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool, StorageError>;
-    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, StorageError>;
+    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<&'a [u8], StorageError>;
+    /// Deletes the value stored under `name`, returning whether there was one.
+    fn remove(&mut self, name: &str) -> Result<bool, StorageError>;
+    /// Deletes every value in the namespace.
+    fn erase_all(&mut self) -> Result<(), StorageError>;
+
+    /// Reads a value of any size into a buffer allocated to fit it.
+    fn get_vec(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+        let mut buf = Vec::new();
+        let required = match self.get_raw(name, &mut buf) {
+            Ok(_) => return Ok(buf),
+            Err(StorageError::BufferTooSmall { required }) => required,
+            Err(e) => return Err(e),
+        };
+
+        buf.resize(required, 0);
+        let len = self.get_raw(name, &mut buf)?.len();
+        buf.truncate(len);
+        Ok(buf)
+    }
 }
 
 pub struct InMemoryStorage {
@@ -38,24 +71,122 @@ impl Storage for InMemoryStorage {
         Ok(true)
     }
 
-    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, StorageError> {
-        match self.storage.get(name) {
-            Some(v) => {
-                match buf.len().cmp(&v.len()) {
-                    std::cmp::Ordering::Equal => {
-                        buf.copy_from_slice(v);
-                    }
-                    std::cmp::Ordering::Greater => {
-                        buf[..v.len()].copy_from_slice(v);
-                    }
-                    std::cmp::Ordering::Less => {
-                        buf.copy_from_slice(&v[..buf.len()]);
-                    }
-                }
-                Ok(Some(buf))
-            }
-            None => Ok(None),
+    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<&'a [u8], StorageError> {
+        let v = self
+            .storage
+            .get(name)
+            .ok_or_else(|| StorageError::NotFound(name.to_string()))?;
+
+        if buf.len() < v.len() {
+            return Err(StorageError::BufferTooSmall { required: v.len() });
         }
+
+        buf[..v.len()].copy_from_slice(v);
+        Ok(&buf[..v.len()])
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool, StorageError> {
+        Ok(self.storage.remove(name).is_some())
+    }
+
+    fn erase_all(&mut self) -> Result<(), StorageError> {
+        self.storage.clear();
+        Ok(())
+    }
+}
+
+/// Behaviour every `Storage` backend is expected to share.
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+
+    /// Runs each check against a fresh storage created by `create` for the
+    /// given namespace.
+    pub fn run<S: Storage>(mut create: impl FnMut(&str) -> S) {
+        round_trips_values(&mut create("round_trip"));
+        overwrites_values(&mut create("overwrite"));
+        stores_empty_values(&mut create("empty"));
+        reports_missing_keys(&mut create("missing"));
+        reports_buffer_too_small(&mut create("too_small"));
+        reads_values_of_any_size(&mut create("any_size"));
+        removes_values(&mut create("remove"));
+        erases_all_values(&mut create("erase_all"));
+    }
+
+    fn round_trips_values(storage: &mut impl Storage) {
+        let mut buf: [u8; 16] = [0; 16];
+        storage.set_raw("key", &[1, 2, 3]).unwrap();
+        assert_eq!(storage.get_raw("key", &mut buf).unwrap(), [1, 2, 3]);
+    }
+
+    fn overwrites_values(storage: &mut impl Storage) {
+        let mut buf: [u8; 16] = [0; 16];
+        storage.set_raw("key", &[1, 2, 3, 4]).unwrap();
+        storage.set_raw("key", &[5, 6]).unwrap();
+        assert_eq!(storage.get_raw("key", &mut buf).unwrap(), [5, 6]);
+    }
+
+    fn stores_empty_values(storage: &mut impl Storage) {
+        let mut buf: [u8; 0] = [];
+        storage.set_raw("key", &[]).unwrap();
+        assert!(storage.get_raw("key", &mut buf).unwrap().is_empty());
+    }
+
+    fn reports_missing_keys(storage: &mut impl Storage) {
+        let mut buf: [u8; 16] = [0; 16];
+        assert!(matches!(
+            storage.get_raw("key", &mut buf),
+            Err(StorageError::NotFound(name)) if name == "key"
+        ));
+    }
+
+    fn reports_buffer_too_small(storage: &mut impl Storage) {
+        let mut buf: [u8; 2] = [0; 2];
+        storage.set_raw("key", &[1, 2, 3]).unwrap();
+        assert!(matches!(
+            storage.get_raw("key", &mut buf),
+            Err(StorageError::BufferTooSmall { required: 3 })
+        ));
+    }
+
+    fn removes_values(storage: &mut impl Storage) {
+        let mut buf: [u8; 16] = [0; 16];
+        storage.set_raw("key", &[1, 2, 3]).unwrap();
+        storage.set_raw("other", &[4]).unwrap();
+        assert!(storage.remove("key").unwrap());
+        assert!(!storage.remove("key").unwrap());
+        assert!(matches!(
+            storage.get_raw("key", &mut buf),
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(storage.get_raw("other", &mut buf).unwrap(), [4]);
+    }
+
+    fn erases_all_values(storage: &mut impl Storage) {
+        let mut buf: [u8; 16] = [0; 16];
+        storage.set_raw("key", &[1, 2, 3]).unwrap();
+        storage.set_raw("other", &[4]).unwrap();
+        storage.erase_all().unwrap();
+        for name in ["key", "other"] {
+            assert!(matches!(
+                storage.get_raw(name, &mut buf),
+                Err(StorageError::NotFound(_))
+            ));
+        }
+        storage.set_raw("key", &[5]).unwrap();
+        assert_eq!(storage.get_raw("key", &mut buf).unwrap(), [5]);
+    }
+
+    fn reads_values_of_any_size(storage: &mut impl Storage) {
+        let value: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        storage.set_raw("key", &value).unwrap();
+        storage.set_raw("empty", &[]).unwrap();
+        assert_eq!(storage.get_vec("key").unwrap(), value);
+        assert!(storage.get_vec("empty").unwrap().is_empty());
+        assert!(matches!(
+            storage.get_vec("missing"),
+            Err(StorageError::NotFound(_))
+        ));
     }
 }
 
@@ -82,10 +213,7 @@ mod tests {
 
         storage.set_raw("test", &value).unwrap();
 
-        assert_eq!(
-            storage.get_raw("test", &mut buf).unwrap().unwrap()[..3],
-            value
-        );
+        assert_eq!(storage.get_raw("test", &mut buf).unwrap(), value);
     }
 
     #[test]
@@ -104,9 +232,40 @@ mod tests {
 
         let buf: &mut [u8] = &mut [0; 100];
 
-        let raw = storage.get_raw("test", buf).unwrap().unwrap();
+        let raw = storage.get_raw("test", buf).unwrap();
         let value = from_bytes::<TestStruct>(raw).unwrap();
 
         assert_eq!(my_struct, value);
     }
+
+    #[test]
+    fn it_passes_storage_conformance() {
+        conformance::run(|_| InMemoryStorage::new());
+    }
+
+    #[test]
+    fn it_reports_missing_keys() {
+        let storage = InMemoryStorage::new();
+
+        let mut buf: [u8; 8] = [0; 8];
+
+        assert!(matches!(
+            storage.get_raw("missing", &mut buf),
+            Err(StorageError::NotFound(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn it_reports_buffer_too_small() {
+        let mut storage = InMemoryStorage::new();
+
+        storage.set_raw("test", &[1, 2, 3, 4, 5]).unwrap();
+
+        let mut buf: [u8; 3] = [0; 3];
+
+        assert!(matches!(
+            storage.get_raw("test", &mut buf),
+            Err(StorageError::BufferTooSmall { required: 5 })
+        ));
+    }
 }
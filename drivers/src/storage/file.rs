@@ -0,0 +1,186 @@
+//! File backed storage
+//!
+//! Keeps every key of a namespace in a single file so settings survive a
+//! restart when the clock logic runs on a host. Writes go to a temporary file
+//! which is then renamed over the original, so a crash mid-write leaves the
+//! previous contents intact.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use postcard::{from_bytes, to_stdvec};
+
+use super::{Storage, StorageError};
+
+pub struct FileStorage {
+    path: PathBuf,
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl AsRef<Path>, namespace: &str) -> Result<Self, StorageError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|e| StorageError::WriteError(Box::new(e)))?;
+
+        let path = dir.join(format!("{}.bin", namespace));
+        let entries = match fs::read(&path) {
+            Ok(bytes) => from_bytes(&bytes).map_err(|_| StorageError::Corrupt)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(StorageError::ReadError(Box::new(e))),
+        };
+
+        Ok(FileStorage { path, entries })
+    }
+
+    fn persist(&self) -> io::Result<()> {
+        let bytes = to_stdvec(&self.entries).map_err(io::Error::other)?;
+
+        let tmp_path = self.path.with_extension("bin.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+impl Storage for FileStorage {
+    fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool, StorageError> {
+        let previous = self.entries.insert(name.to_string(), buf.to_vec());
+
+        if let Err(e) = self.persist() {
+            match previous {
+                Some(v) => self.entries.insert(name.to_string(), v),
+                None => self.entries.remove(name),
+            };
+            return Err(StorageError::WriteError(Box::new(e)));
+        }
+
+        Ok(true)
+    }
+
+    fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<&'a [u8], StorageError> {
+        let v = self
+            .entries
+            .get(name)
+            .ok_or_else(|| StorageError::NotFound(name.to_string()))?;
+
+        if buf.len() < v.len() {
+            return Err(StorageError::BufferTooSmall { required: v.len() });
+        }
+
+        buf[..v.len()].copy_from_slice(v);
+        Ok(&buf[..v.len()])
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool, StorageError> {
+        let Some(previous) = self.entries.remove(name) else {
+            return Ok(false);
+        };
+
+        if let Err(e) = self.persist() {
+            self.entries.insert(name.to_string(), previous);
+            return Err(StorageError::WriteError(Box::new(e)));
+        }
+
+        Ok(true)
+    }
+
+    fn erase_all(&mut self) -> Result<(), StorageError> {
+        let previous = std::mem::take(&mut self.entries);
+
+        if let Err(e) = self.persist() {
+            self.entries = previous;
+            return Err(StorageError::WriteError(Box::new(e)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance;
+
+    #[test]
+    fn it_passes_storage_conformance() {
+        let dir = tempfile::tempdir().unwrap();
+
+        conformance::run(|namespace| FileStorage::new(dir.path(), namespace).unwrap());
+    }
+
+    #[test]
+    fn it_survives_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut storage = FileStorage::new(dir.path(), "config").unwrap();
+        storage.set_raw("a", &[1, 2, 3]).unwrap();
+        storage.set_raw("b", &[4, 5]).unwrap();
+        drop(storage);
+
+        let storage = FileStorage::new(dir.path(), "config").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(storage.get_raw("a", &mut buf).unwrap(), [1, 2, 3]);
+        assert_eq!(storage.get_raw("b", &mut buf).unwrap(), [4, 5]);
+    }
+
+    #[test]
+    fn it_persists_removals() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut storage = FileStorage::new(dir.path(), "config").unwrap();
+        storage.set_raw("a", &[1, 2, 3]).unwrap();
+        storage.remove("a").unwrap();
+        drop(storage);
+
+        let storage = FileStorage::new(dir.path(), "config").unwrap();
+        let mut buf = [0; 8];
+        assert!(matches!(
+            storage.get_raw("a", &mut buf),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn it_keeps_namespaces_separate() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut first = FileStorage::new(dir.path(), "first").unwrap();
+        first.set_raw("key", &[1]).unwrap();
+
+        let second = FileStorage::new(dir.path(), "second").unwrap();
+        let mut buf = [0; 8];
+        assert!(matches!(
+            second.get_raw("key", &mut buf),
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn it_leaves_no_temporary_file_behind() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut storage = FileStorage::new(dir.path(), "config").unwrap();
+        storage.set_raw("a", &[1]).unwrap();
+
+        let files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["config.bin"]);
+    }
+
+    #[test]
+    fn it_reports_a_corrupt_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("config.bin"), [0xff, 0xff, 0xff]).unwrap();
+
+        assert!(matches!(
+            FileStorage::new(dir.path(), "config"),
+            Err(StorageError::Corrupt)
+        ));
+    }
+}
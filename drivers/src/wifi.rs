@@ -0,0 +1,346 @@
+//! WiFi Network Selection
+//!
+//! Picks which of the saved networks to join from the results of a scan, and
+//! keeps the clock connected to it.
+
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::config::{validate_wifi_pass, validate_wifi_ssid};
+
+mod access_point;
+mod connection;
+mod trial;
+
+pub use access_point::{generate_ap_password, AccessPoint, ApMode, AP_PASSWORD_LEN};
+pub use connection::{
+    ConnectionManager, ConnectionState, LinkEvent, RetryPolicy, WifiDriver, SCAN_MAX_AGE,
+    SCAN_TIMEOUT,
+};
+pub use trial::{ChangeResult, CredentialTrial, TrialDecision};
+
+/// Most networks that can be saved besides the primary one.
+pub const MAX_KNOWN_NETWORKS: usize = 8;
+
+/// What the clock reports about its WiFi connection.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct WifiStatus {
+    pub connection: ConnectionState,
+    #[serde(rename = "lastChange")]
+    pub last_change: Option<ChangeResult>,
+}
+
+impl Default for WifiStatus {
+    fn default() -> Self {
+        WifiStatus {
+            connection: ConnectionState::Idle,
+            last_change: None,
+        }
+    }
+}
+
+/// An access point seen during a scan.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ScanResult {
+    pub ssid: String,
+    pub rssi: i8,
+    pub channel: u8,
+    /// `Auto` when the access point uses a method that can't be picked
+    /// explicitly.
+    #[serde(default)]
+    pub auth: WifiAuth,
+}
+
+/// How the clock authenticates with an access point.
+///
+/// Stored configs encode the variant by position, so new methods must be
+/// added at the end.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum WifiAuth {
+    /// Let the WiFi driver pick from what the access point advertises.
+    #[default]
+    Auto,
+    Open,
+    /// WPA2-PSK
+    Wpa2Personal,
+    /// WPA2/WPA3 transition mode
+    Wpa2Wpa3Personal,
+    /// WPA3-SAE
+    Wpa3Personal,
+}
+
+impl WifiAuth {
+    pub fn is_auto(&self) -> bool {
+        *self == WifiAuth::Auto
+    }
+
+    /// Checks that a password suits this method: open networks take none,
+    /// and WPA passphrases are 8 to 63 printable ASCII characters. WPA2-PSK
+    /// also accepts the raw key as 64 hex digits.
+    pub fn validate_password(&self, pass: &str) -> Result<(), ValidationError> {
+        let passphrase = (8..=63).contains(&pass.len())
+            && pass.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+        let raw_key = pass.len() == 64 && pass.bytes().all(|b| b.is_ascii_hexdigit());
+
+        let valid = match self {
+            WifiAuth::Auto => true,
+            WifiAuth::Open => pass.is_empty(),
+            WifiAuth::Wpa2Personal => passphrase || raw_key,
+            WifiAuth::Wpa2Wpa3Personal | WifiAuth::Wpa3Personal => passphrase,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(ValidationError::new("invalid_password_for_auth"))
+        }
+    }
+}
+
+/// A saved network. When several are in range, the one with the highest
+/// priority is joined, and among equal priorities the strongest signal wins.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Validate)]
+#[validate(schema(
+    function = "validate_network_auth",
+    message = "password does not suit the authentication method"
+))]
+pub struct KnownNetwork {
+    #[validate(
+        length(min = 1, message = "SSID must not be blank"),
+        custom(function = "validate_wifi_ssid", message = "SSID is too long")
+    )]
+    pub ssid: String,
+    #[validate(custom(function = "validate_wifi_pass", message = "password is too long"))]
+    #[serde(rename = "password")]
+    pub pass: String,
+    #[serde(default)]
+    pub auth: WifiAuth,
+    pub priority: u8,
+}
+
+impl KnownNetwork {
+    pub fn new(ssid: &str, pass: &str, priority: u8) -> Self {
+        KnownNetwork {
+            ssid: String::from(ssid),
+            pass: String::from(pass),
+            auth: WifiAuth::Auto,
+            priority,
+        }
+    }
+
+    pub fn with_auth(mut self, auth: WifiAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), validator::ValidationErrors> {
+        Validate::validate(self)
+    }
+}
+
+fn validate_network_auth(network: &KnownNetwork) -> Result<(), ValidationError> {
+    network.auth.validate_password(&network.pass)
+}
+
+/// Returns the known network to join out of those found by a scan, or `None`
+/// if none of them are in range.
+pub fn select_network<'a>(
+    known: &'a [KnownNetwork],
+    scan: &[ScanResult],
+) -> Option<&'a KnownNetwork> {
+    known
+        .iter()
+        .filter_map(|network| {
+            scan.iter()
+                .filter(|ap| ap.ssid == network.ssid)
+                .map(|ap| ap.rssi)
+                .max()
+                .map(|rssi| (network, rssi))
+        })
+        .max_by_key(|(network, rssi)| (network.priority, *rssi))
+        .map(|(network, _)| network)
+}
+
+/// Lists the networks found by a scan for someone to pick from: one entry
+/// per SSID with its strongest access point, strongest first. Hidden networks
+/// are left out since they can't be picked by name.
+pub fn nearby_networks(scan: Vec<ScanResult>) -> Vec<ScanResult> {
+    let mut nearby: Vec<ScanResult> = Vec::new();
+    for ap in scan.into_iter().filter(|ap| !ap.ssid.is_empty()) {
+        match nearby.iter_mut().find(|seen| seen.ssid == ap.ssid) {
+            Some(seen) if seen.rssi < ap.rssi => *seen = ap,
+            Some(_) => {}
+            None => nearby.push(ap),
+        }
+    }
+
+    nearby.sort_by(|a, b| b.rssi.cmp(&a.rssi).then_with(|| a.ssid.cmp(&b.ssid)));
+    nearby
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ap(ssid: &str, rssi: i8) -> ScanResult {
+        ScanResult {
+            ssid: String::from(ssid),
+            rssi,
+            channel: 1,
+            auth: WifiAuth::Wpa2Personal,
+        }
+    }
+
+    #[test]
+    fn it_selects_nothing_when_no_known_network_is_in_range() {
+        let known = [KnownNetwork::new("home", "pass", 0)];
+        let scan = [ap("neighbour", -40)];
+
+        assert_eq!(select_network(&known, &scan), None);
+    }
+
+    #[test]
+    fn it_prefers_higher_priority_networks() {
+        let known = [
+            KnownNetwork::new("office", "pass", 1),
+            KnownNetwork::new("home", "pass", 5),
+        ];
+        let scan = [ap("office", -30), ap("home", -80)];
+
+        assert_eq!(select_network(&known, &scan).unwrap().ssid, "home");
+    }
+
+    #[test]
+    fn it_prefers_stronger_signal_at_equal_priority() {
+        let known = [
+            KnownNetwork::new("office", "pass", 1),
+            KnownNetwork::new("booth", "pass", 1),
+        ];
+        let scan = [ap("office", -70), ap("booth", -50)];
+
+        assert_eq!(select_network(&known, &scan).unwrap().ssid, "booth");
+    }
+
+    #[test]
+    fn it_uses_the_strongest_access_point_of_a_network() {
+        let known = [
+            KnownNetwork::new("office", "pass", 1),
+            KnownNetwork::new("booth", "pass", 1),
+        ];
+        let scan = [ap("office", -90), ap("booth", -60), ap("office", -40)];
+
+        assert_eq!(select_network(&known, &scan).unwrap().ssid, "office");
+    }
+
+    #[test]
+    fn it_accepts_any_password_for_auto() {
+        assert!(WifiAuth::Auto.validate_password("").is_ok());
+        assert!(WifiAuth::Auto.validate_password("short").is_ok());
+    }
+
+    #[test]
+    fn it_requires_no_password_for_open_networks() {
+        assert!(WifiAuth::Open.validate_password("").is_ok());
+        assert!(WifiAuth::Open.validate_password("password").is_err());
+    }
+
+    #[test]
+    fn it_requires_a_passphrase_for_wpa() {
+        for auth in [
+            WifiAuth::Wpa2Personal,
+            WifiAuth::Wpa2Wpa3Personal,
+            WifiAuth::Wpa3Personal,
+        ] {
+            assert!(auth.validate_password("").is_err(), "{:?}", auth);
+            assert!(auth.validate_password("1234567").is_err(), "{:?}", auth);
+            assert!(auth.validate_password("12345678").is_ok(), "{:?}", auth);
+            assert!(
+                auth.validate_password("correct horse").is_ok(),
+                "{:?}",
+                auth
+            );
+            assert!(
+                auth.validate_password(&"p".repeat(63)).is_ok(),
+                "{:?}",
+                auth
+            );
+            assert!(auth.validate_password("pässword").is_err(), "{:?}", auth);
+        }
+    }
+
+    #[test]
+    fn it_accepts_a_raw_key_only_for_wpa2() {
+        let key = "0123456789abcdef".repeat(4);
+
+        assert!(WifiAuth::Wpa2Personal.validate_password(&key).is_ok());
+        assert!(WifiAuth::Wpa2Wpa3Personal.validate_password(&key).is_err());
+        assert!(WifiAuth::Wpa3Personal.validate_password(&key).is_err());
+        assert!(WifiAuth::Wpa2Personal
+            .validate_password(&"z".repeat(64))
+            .is_err());
+    }
+
+    #[test]
+    fn it_validates_the_password_of_known_networks_against_their_auth() {
+        let network = KnownNetwork::new("home", "short", 0);
+
+        assert!(network.clone().validate().is_ok());
+        assert!(network
+            .clone()
+            .with_auth(WifiAuth::Wpa2Personal)
+            .validate()
+            .is_err());
+        assert!(network.with_auth(WifiAuth::Open).validate().is_err());
+    }
+
+    #[test]
+    fn it_validates_known_networks() {
+        assert!(KnownNetwork::new("home", "pass", 0).validate().is_ok());
+        assert!(KnownNetwork::new("", "pass", 0).validate().is_err());
+        assert!(KnownNetwork::new(&"s".repeat(33), "pass", 0)
+            .validate()
+            .is_err());
+        assert!(KnownNetwork::new("home", &"p".repeat(65), 0)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn it_lists_each_nearby_network_once_at_its_strongest() {
+        let scan = vec![
+            ap("home", -70),
+            ap("office", -60),
+            ap("home", -45),
+            ap("home", -80),
+        ];
+
+        assert_eq!(
+            nearby_networks(scan),
+            vec![ap("home", -45), ap("office", -60)]
+        );
+    }
+
+    #[test]
+    fn it_sorts_nearby_networks_by_signal_then_name() {
+        let scan = vec![
+            ap("weak", -90),
+            ap("b", -50),
+            ap("strong", -30),
+            ap("a", -50),
+        ];
+
+        let ssids: Vec<String> = nearby_networks(scan)
+            .into_iter()
+            .map(|ap| ap.ssid)
+            .collect();
+        assert_eq!(ssids, ["strong", "a", "b", "weak"]);
+    }
+
+    #[test]
+    fn it_leaves_hidden_networks_out_of_nearby_networks() {
+        let scan = vec![ap("", -30), ap("home", -60)];
+
+        assert_eq!(nearby_networks(scan), vec![ap("home", -60)]);
+    }
+}
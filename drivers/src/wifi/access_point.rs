@@ -0,0 +1,232 @@
+//! Setup Access Point
+//!
+//! The clock can broadcast its own network so it can be set up before it
+//! knows any other. By default the access point only runs while the clock
+//! can't reach a network, and each clock protects it with a password of its
+//! own, generated on first boot and shown on the tubes.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::ConnectionState;
+use crate::secrets::SecretError;
+
+/// Number of digits in a generated access point password, the fewest WPA2
+/// allows.
+pub const AP_PASSWORD_LEN: usize = 8;
+
+/// When the setup access point is broadcast.
+///
+/// Stored configs encode the variant by position, so new modes must be added
+/// at the end.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ApMode {
+    Always,
+    /// Only while the clock isn't connected to a network.
+    #[default]
+    WhenDisconnected,
+    /// Never. A factory reset brings the access point back.
+    Never,
+}
+
+impl ApMode {
+    pub fn is_default(&self) -> bool {
+        *self == ApMode::default()
+    }
+}
+
+/// Decides when the access point should run. A change of connection has to
+/// last for the grace period before the access point follows it, so a phone
+/// on the access point can see setup succeed and a brief dropout doesn't
+/// bring it back.
+pub struct AccessPoint {
+    mode: ApMode,
+    grace: Duration,
+    enabled: bool,
+    changing_since: Option<Instant>,
+}
+
+impl AccessPoint {
+    pub fn new(mode: ApMode, grace: Duration) -> Self {
+        AccessPoint {
+            mode,
+            grace,
+            enabled: mode != ApMode::Never,
+            changing_since: None,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: ApMode) {
+        self.mode = mode;
+        self.changing_since = None;
+        match mode {
+            ApMode::Always => self.enabled = true,
+            ApMode::Never => self.enabled = false,
+            ApMode::WhenDisconnected => {}
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns whether the access point should be running.
+    pub fn update(&mut self, state: &ConnectionState, now: Instant) -> bool {
+        if self.mode != ApMode::WhenDisconnected {
+            return self.enabled;
+        }
+
+        let wanted = !matches!(state, ConnectionState::Connected { .. });
+        if wanted == self.enabled {
+            self.changing_since = None;
+            return self.enabled;
+        }
+
+        let since = *self.changing_since.get_or_insert(now);
+        if now.saturating_duration_since(since) >= self.grace {
+            self.enabled = wanted;
+            self.changing_since = None;
+        }
+        self.enabled
+    }
+}
+
+/// Generates a random numeric password for the access point. Digits are
+/// easy to read off the tubes and to type on a phone.
+pub fn generate_ap_password() -> Result<String, SecretError> {
+    loop {
+        let mut random = [0; 4];
+        getrandom::getrandom(&mut random).map_err(|_| SecretError::Random)?;
+        if let Some(password) = ap_password_from(u32::from_le_bytes(random)) {
+            return Ok(password);
+        }
+    }
+}
+
+/// Turns a random number into a password, or `None` if it falls in the
+/// range that would make some passwords more likely than others.
+fn ap_password_from(random: u32) -> Option<String> {
+    const RANGE: u32 = 10u32.pow(AP_PASSWORD_LEN as u32);
+    const LIMIT: u32 = u32::MAX / RANGE * RANGE;
+
+    if random >= LIMIT {
+        return None;
+    }
+    Some(format!(
+        "{:0width$}",
+        random % RANGE,
+        width = AP_PASSWORD_LEN
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(60);
+
+    fn connected() -> ConnectionState {
+        ConnectionState::Connected {
+            ssid: String::from("home"),
+        }
+    }
+
+    fn connecting() -> ConnectionState {
+        ConnectionState::Connecting {
+            ssid: String::from("home"),
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_access_point_up_when_always_on() {
+        let now = Instant::now();
+        let mut ap = AccessPoint::new(ApMode::Always, GRACE);
+
+        assert!(ap.update(&connected(), now));
+        assert!(ap.update(&connected(), now + GRACE * 2));
+    }
+
+    #[test]
+    fn it_never_starts_the_access_point_when_off() {
+        let now = Instant::now();
+        let mut ap = AccessPoint::new(ApMode::Never, GRACE);
+
+        assert!(!ap.update(&ConnectionState::Failed, now));
+        assert!(!ap.update(&ConnectionState::Failed, now + GRACE * 2));
+    }
+
+    #[test]
+    fn it_starts_with_the_access_point_up_until_connected() {
+        let now = Instant::now();
+        let mut ap = AccessPoint::new(ApMode::WhenDisconnected, GRACE);
+
+        assert!(ap.update(&connecting(), now));
+        assert!(ap.update(&connecting(), now + GRACE * 2));
+    }
+
+    #[test]
+    fn it_shuts_the_access_point_off_after_connecting_for_the_grace_period() {
+        let now = Instant::now();
+        let mut ap = AccessPoint::new(ApMode::WhenDisconnected, GRACE);
+
+        assert!(ap.update(&connected(), now));
+        assert!(ap.update(&connected(), now + GRACE - Duration::from_secs(1)));
+        assert!(!ap.update(&connected(), now + GRACE));
+    }
+
+    #[test]
+    fn it_brings_the_access_point_back_after_a_lasting_disconnect() {
+        let now = Instant::now();
+        let mut ap = AccessPoint::new(ApMode::WhenDisconnected, GRACE);
+        ap.update(&connected(), now);
+        let now = now + GRACE;
+        assert!(!ap.update(&connected(), now));
+
+        // A brief dropout restarts the grace period
+        assert!(!ap.update(&connecting(), now + Duration::from_secs(10)));
+        assert!(!ap.update(&connected(), now + Duration::from_secs(20)));
+        assert!(!ap.update(&connecting(), now + Duration::from_secs(30)));
+        assert!(!ap.update(&connecting(), now + Duration::from_secs(89)));
+
+        assert!(ap.update(&connecting(), now + Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn it_follows_mode_changes() {
+        let now = Instant::now();
+        let mut ap = AccessPoint::new(ApMode::WhenDisconnected, GRACE);
+
+        ap.set_mode(ApMode::Never);
+        assert!(!ap.update(&connecting(), now));
+
+        ap.set_mode(ApMode::Always);
+        assert!(ap.update(&connected(), now));
+
+        ap.set_mode(ApMode::WhenDisconnected);
+        assert!(ap.update(&connected(), now));
+        assert!(!ap.update(&connected(), now + GRACE));
+    }
+
+    #[test]
+    fn it_generates_numeric_passwords() {
+        let password = generate_ap_password().unwrap();
+
+        assert_eq!(password.len(), AP_PASSWORD_LEN);
+        assert!(password.bytes().all(|b| b.is_ascii_digit()));
+    }
+
+    #[test]
+    fn it_pads_passwords_with_zeros() {
+        assert_eq!(ap_password_from(42).unwrap(), "00000042");
+        assert_eq!(ap_password_from(123_456_789).unwrap(), "23456789");
+    }
+
+    #[test]
+    fn it_rejects_random_numbers_that_would_bias_passwords() {
+        assert!(ap_password_from(4_199_999_999).is_some());
+        assert!(ap_password_from(4_200_000_000).is_none());
+        assert!(ap_password_from(u32::MAX).is_none());
+    }
+}
@@ -0,0 +1,705 @@
+//! WiFi Connection Management
+//!
+//! Keeps the clock connected without blocking the main loop. Each call to
+//! `poll` checks on the scan or attempt in progress: a connection that does
+//! not come up in time is retried after an exponentially growing delay, and a
+//! connection the WiFi stack reports as dropped is re-established straight
+//! away. Scans run in the background and their results are reused for a
+//! while, so retries don't wait for a new one each time.
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use super::{select_network, KnownNetwork, ScanResult};
+
+/// The parts of a WiFi stack the connection manager drives.
+pub trait WifiDriver {
+    type Error: Debug;
+
+    /// Starts scanning for networks without waiting for the scan to finish.
+    fn start_scan(&mut self) -> Result<(), Self::Error>;
+    /// What the scan in progress found, once it has finished.
+    fn scan_results(&mut self) -> Option<Result<Vec<ScanResult>, Self::Error>>;
+    /// Starts joining a network without waiting for it to come up.
+    fn connect(&mut self, network: &KnownNetwork) -> Result<(), Self::Error>;
+    fn disconnect(&mut self) -> Result<(), Self::Error>;
+    /// The next change to the link reported by the WiFi stack, if any.
+    fn next_event(&mut self) -> Option<LinkEvent>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LinkEvent {
+    /// The link is up and has an address.
+    Up,
+    Down,
+}
+
+/// How long scan results are used to pick a network before scanning again.
+pub const SCAN_MAX_AGE: Duration = Duration::from_secs(60);
+/// How long to wait for a scan before joining without one.
+pub const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How long an attempt may take before it counts as failed.
+    pub connect_timeout: Duration,
+    /// Delay after the first failed attempt, doubled after each further one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Consecutive failed attempts before giving up, or `None` to keep trying.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            connect_timeout: Duration::from_secs(20),
+            initial_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(300),
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt after `failures` failed attempts in a row.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum ConnectionState {
+    /// Not trying to connect.
+    Idle,
+    /// Looking for networks before picking one to join.
+    Scanning,
+    Connecting {
+        ssid: String,
+    },
+    Connected {
+        ssid: String,
+    },
+    /// Waiting to retry after `failures` failed attempts in a row.
+    Backoff {
+        failures: u32,
+    },
+    /// Gave up, either because there is no network to join or the retry
+    /// policy ran out of attempts. Starts over on the next `start`.
+    Failed,
+}
+
+pub struct ConnectionManager {
+    policy: RetryPolicy,
+    networks: Vec<KnownNetwork>,
+    state: ConnectionState,
+    failures: u32,
+    /// End of the current scan, connection attempt or backoff.
+    deadline: Instant,
+    /// The networks found by the last scan that finished.
+    last_scan: Option<Scan>,
+    /// When the scan in progress is given up on, if there is one.
+    scan_deadline: Option<Instant>,
+}
+
+struct Scan {
+    results: Vec<ScanResult>,
+    finished: Instant,
+}
+
+impl ConnectionManager {
+    pub fn new(policy: RetryPolicy) -> Self {
+        ConnectionManager {
+            policy,
+            networks: Vec::new(),
+            state: ConnectionState::Idle,
+            failures: 0,
+            deadline: Instant::now(),
+            last_scan: None,
+            scan_deadline: None,
+        }
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// Starts a scan in the background unless one is running already. Its
+    /// results are also used to pick the next network to join.
+    pub fn scan<W: WifiDriver>(&mut self, driver: &mut W, now: Instant) {
+        if self.scan_deadline.is_some() {
+            return;
+        }
+        match driver.start_scan() {
+            Ok(()) => self.scan_deadline = Some(now + SCAN_TIMEOUT),
+            Err(e) => warn!("Error scanning for WiFi networks: {:?}", e),
+        }
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scan_deadline.is_some()
+    }
+
+    /// The networks found by the last scan that finished.
+    pub fn scan_results(&self) -> &[ScanResult] {
+        self.last_scan
+            .as_ref()
+            .map_or(&[], |scan| scan.results.as_slice())
+    }
+
+    /// Starts connecting to the best of `networks`, replacing whatever the
+    /// manager was doing before.
+    pub fn start<W: WifiDriver>(
+        &mut self,
+        driver: &mut W,
+        networks: Vec<KnownNetwork>,
+        now: Instant,
+    ) -> &ConnectionState {
+        self.networks = networks;
+        self.failures = 0;
+        self.attempt(driver, now);
+        &self.state
+    }
+
+    pub fn stop<W: WifiDriver>(&mut self, driver: &mut W) {
+        if let Err(e) = driver.disconnect() {
+            warn!("Error disconnecting from WiFi: {:?}", e);
+        }
+        self.state = ConnectionState::Idle;
+    }
+
+    pub fn poll<W: WifiDriver>(&mut self, driver: &mut W, now: Instant) -> &ConnectionState {
+        self.poll_scan(driver, now);
+        while let Some(event) = driver.next_event() {
+            self.handle(driver, event, now);
+        }
+
+        match &self.state {
+            ConnectionState::Idle | ConnectionState::Connected { .. } | ConnectionState::Failed => {
+            }
+            ConnectionState::Scanning => {
+                if !self.is_scanning() {
+                    self.connect(driver, now);
+                }
+            }
+            ConnectionState::Connecting { ssid } => {
+                if now >= self.deadline {
+                    warn!("Timed out connecting to {}", ssid);
+                    if let Err(e) = driver.disconnect() {
+                        warn!("Error disconnecting from WiFi: {:?}", e);
+                    }
+                    self.fail(now);
+                }
+            }
+            ConnectionState::Backoff { .. } => {
+                if now >= self.deadline {
+                    self.attempt(driver, now);
+                }
+            }
+        }
+        &self.state
+    }
+
+    fn poll_scan<W: WifiDriver>(&mut self, driver: &mut W, now: Instant) {
+        let Some(deadline) = self.scan_deadline else {
+            return;
+        };
+        match driver.scan_results() {
+            Some(Ok(results)) => {
+                self.last_scan = Some(Scan {
+                    results,
+                    finished: now,
+                });
+            }
+            Some(Err(e)) => warn!("Error scanning for WiFi networks: {:?}", e),
+            None if now >= deadline => warn!("Timed out scanning for WiFi networks"),
+            None => return,
+        }
+        self.scan_deadline = None;
+    }
+
+    fn handle<W: WifiDriver>(&mut self, driver: &mut W, event: LinkEvent, now: Instant) {
+        match (&self.state, event) {
+            (ConnectionState::Connecting { ssid }, LinkEvent::Up) => {
+                info!("Connected to {}", ssid);
+                self.failures = 0;
+                self.state = ConnectionState::Connected { ssid: ssid.clone() };
+            }
+            (ConnectionState::Connecting { ssid }, LinkEvent::Down) => {
+                warn!("Could not connect to {}", ssid);
+                self.fail(now);
+            }
+            (ConnectionState::Connected { ssid }, LinkEvent::Down) => {
+                warn!("Lost connection to {}, reconnecting", ssid);
+                self.failures = 0;
+                self.attempt(driver, now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Joins the best network right away when a recent scan shows which
+    /// that is, or scans first.
+    fn attempt<W: WifiDriver>(&mut self, driver: &mut W, now: Instant) {
+        let fresh = self
+            .last_scan
+            .as_ref()
+            .is_some_and(|scan| now.duration_since(scan.finished) < SCAN_MAX_AGE);
+        if fresh {
+            self.connect(driver, now);
+            return;
+        }
+
+        self.scan(driver, now);
+        if self.is_scanning() {
+            self.state = ConnectionState::Scanning;
+        } else {
+            self.connect(driver, now);
+        }
+    }
+
+    fn connect<W: WifiDriver>(&mut self, driver: &mut W, now: Instant) {
+        // A network missing from the scan may still be hidden, so fall back
+        // to the one with the highest priority.
+        let Some(network) = select_network(&self.networks, self.scan_results())
+            .or_else(|| self.networks.iter().max_by_key(|n| n.priority))
+            .cloned()
+        else {
+            warn!("No WiFi networks configured");
+            self.state = ConnectionState::Failed;
+            return;
+        };
+
+        // Link changes from before this attempt say nothing about how it goes
+        while driver.next_event().is_some() {}
+
+        info!("Connecting to {}", network.ssid);
+        match driver.connect(&network) {
+            Ok(()) => {
+                self.deadline = now + self.policy.connect_timeout;
+                self.state = ConnectionState::Connecting { ssid: network.ssid };
+            }
+            Err(e) => {
+                warn!("Error connecting to {}: {:?}", network.ssid, e);
+                self.fail(now);
+            }
+        }
+    }
+
+    fn fail(&mut self, now: Instant) {
+        self.failures += 1;
+        if self
+            .policy
+            .max_attempts
+            .is_some_and(|max| self.failures >= max)
+        {
+            warn!("Giving up on WiFi after {} attempts", self.failures);
+            self.state = ConnectionState::Failed;
+            return;
+        }
+
+        let backoff = self.policy.backoff(self.failures);
+        info!("Retrying WiFi in {:?}", backoff);
+        self.deadline = now + backoff;
+        self.state = ConnectionState::Backoff {
+            failures: self.failures,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wifi::WifiAuth;
+
+    /// Finishes scans on the next poll, unless `slow_scans` is set, and
+    /// comes up on the next poll after `connect` when `reachable` lists the
+    /// network.
+    #[derive(Default)]
+    struct FakeWifi {
+        in_range: Vec<ScanResult>,
+        reachable: Vec<String>,
+        connected_to: Option<String>,
+        connects: Vec<String>,
+        scans: u32,
+        scanning: bool,
+        slow_scans: bool,
+        events: Vec<LinkEvent>,
+        fail_connect: bool,
+    }
+
+    impl FakeWifi {
+        fn new(in_range: &[&str]) -> Self {
+            FakeWifi {
+                in_range: in_range
+                    .iter()
+                    .map(|ssid| ScanResult {
+                        ssid: ssid.to_string(),
+                        rssi: -50,
+                        channel: 1,
+                        auth: WifiAuth::Auto,
+                    })
+                    .collect(),
+                reachable: in_range.iter().map(|ssid| ssid.to_string()).collect(),
+                ..Default::default()
+            }
+        }
+
+        fn drop_connection(&mut self) {
+            self.connected_to = None;
+            self.events.push(LinkEvent::Down);
+        }
+    }
+
+    impl WifiDriver for FakeWifi {
+        type Error = &'static str;
+
+        fn start_scan(&mut self) -> Result<(), Self::Error> {
+            self.scans += 1;
+            self.scanning = true;
+            Ok(())
+        }
+
+        fn scan_results(&mut self) -> Option<Result<Vec<ScanResult>, Self::Error>> {
+            if !self.scanning || self.slow_scans {
+                return None;
+            }
+            self.scanning = false;
+            Some(Ok(self.in_range.clone()))
+        }
+
+        fn connect(&mut self, network: &KnownNetwork) -> Result<(), Self::Error> {
+            self.connects.push(network.ssid.clone());
+            if self.fail_connect {
+                return Err("connect failed");
+            }
+            if self.reachable.contains(&network.ssid) {
+                self.connected_to = Some(network.ssid.clone());
+                self.events.push(LinkEvent::Up);
+            }
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.connected_to = None;
+            Ok(())
+        }
+
+        fn next_event(&mut self) -> Option<LinkEvent> {
+            (!self.events.is_empty()).then(|| self.events.remove(0))
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            connect_timeout: Duration::from_secs(10),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(8),
+            max_attempts: None,
+        }
+    }
+
+    fn networks() -> Vec<KnownNetwork> {
+        vec![
+            KnownNetwork::new("home", "pass", 1),
+            KnownNetwork::new("office", "pass", 2),
+        ]
+    }
+
+    fn connecting(ssid: &str) -> ConnectionState {
+        ConnectionState::Connecting {
+            ssid: ssid.to_string(),
+        }
+    }
+
+    fn connected(ssid: &str) -> ConnectionState {
+        ConnectionState::Connected {
+            ssid: ssid.to_string(),
+        }
+    }
+
+    #[test]
+    fn it_starts_idle() {
+        let mut wifi = FakeWifi::new(&["home"]);
+        let mut manager = ConnectionManager::new(policy());
+
+        assert_eq!(
+            manager.poll(&mut wifi, Instant::now()),
+            &ConnectionState::Idle
+        );
+        assert!(wifi.connects.is_empty());
+    }
+
+    #[test]
+    fn it_connects_to_the_best_network_in_range() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        let mut manager = ConnectionManager::new(policy());
+
+        assert_eq!(
+            manager.start(&mut wifi, networks(), now),
+            &ConnectionState::Scanning
+        );
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("home"));
+        assert_eq!(manager.poll(&mut wifi, now), &connected("home"));
+    }
+
+    #[test]
+    fn it_tries_a_network_missing_from_the_scan() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&[]);
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), now);
+        manager.poll(&mut wifi, now);
+
+        assert_eq!(wifi.connects, ["office"]);
+    }
+
+    #[test]
+    fn it_connects_without_a_scan_that_does_not_finish() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        wifi.slow_scans = true;
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), now);
+        let almost = now + SCAN_TIMEOUT - Duration::from_millis(1);
+        assert_eq!(manager.poll(&mut wifi, almost), &ConnectionState::Scanning);
+
+        assert_eq!(
+            manager.poll(&mut wifi, now + SCAN_TIMEOUT),
+            &connecting("office")
+        );
+    }
+
+    #[test]
+    fn it_reuses_recent_scans() {
+        let start = Instant::now();
+        let mut wifi = FakeWifi::new(&[]);
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), start);
+        manager.poll(&mut wifi, start);
+        // Times out, backs off and tries again without scanning
+        let now = start + Duration::from_secs(10);
+        manager.poll(&mut wifi, now);
+        let now = now + Duration::from_secs(1);
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("office"));
+        assert_eq!(wifi.scans, 1);
+
+        let now = start + SCAN_MAX_AGE + Duration::from_secs(10);
+        manager.poll(&mut wifi, now);
+        let now = now + Duration::from_secs(8);
+        assert_eq!(manager.poll(&mut wifi, now), &ConnectionState::Scanning);
+        assert_eq!(wifi.scans, 2);
+    }
+
+    #[test]
+    fn it_shares_scans_with_callers() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        let mut manager = ConnectionManager::new(policy());
+        manager.start(&mut wifi, networks(), now);
+
+        // One is running already
+        manager.scan(&mut wifi, now);
+        assert!(manager.is_scanning());
+        assert_eq!(wifi.scans, 1);
+
+        manager.poll(&mut wifi, now);
+        assert!(!manager.is_scanning());
+        assert_eq!(manager.scan_results(), &wifi.in_range[..]);
+    }
+
+    #[test]
+    fn it_backs_off_exponentially_after_timeouts() {
+        let start = Instant::now();
+        let mut wifi = FakeWifi::new(&[]);
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), start);
+        manager.poll(&mut wifi, start);
+        let mut now = start + Duration::from_secs(9);
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("office"));
+
+        for (failures, backoff) in [(1, 1), (2, 2), (3, 4), (4, 8), (5, 8)] {
+            now += Duration::from_secs(1);
+            assert_eq!(
+                manager.poll(&mut wifi, now),
+                &ConnectionState::Backoff { failures }
+            );
+
+            now += Duration::from_secs(backoff) - Duration::from_millis(1);
+            assert_eq!(
+                manager.poll(&mut wifi, now),
+                &ConnectionState::Backoff { failures }
+            );
+
+            now += Duration::from_millis(1);
+            // Scans again once the last results are too old
+            manager.poll(&mut wifi, now);
+            assert_eq!(manager.poll(&mut wifi, now), &connecting("office"));
+            now += Duration::from_secs(9);
+        }
+    }
+
+    #[test]
+    fn it_connects_once_the_network_comes_back() {
+        let start = Instant::now();
+        let mut wifi = FakeWifi::new(&[]);
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), start);
+        manager.poll(&mut wifi, start);
+        let now = start + Duration::from_secs(10);
+        manager.poll(&mut wifi, now);
+
+        wifi.reachable.push(String::from("office"));
+        let now = now + Duration::from_secs(1);
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("office"));
+        assert_eq!(manager.poll(&mut wifi, now), &connected("office"));
+    }
+
+    #[test]
+    fn it_backs_off_when_connect_fails() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        wifi.fail_connect = true;
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), now);
+
+        assert_eq!(
+            manager.poll(&mut wifi, now),
+            &ConnectionState::Backoff { failures: 1 }
+        );
+    }
+
+    #[test]
+    fn it_backs_off_as_soon_as_a_connect_is_refused() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        wifi.reachable.clear();
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), now);
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("home"));
+
+        wifi.events.push(LinkEvent::Down);
+        assert_eq!(
+            manager.poll(&mut wifi, now),
+            &ConnectionState::Backoff { failures: 1 }
+        );
+    }
+
+    #[test]
+    fn it_ignores_link_changes_from_before_an_attempt() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        let mut manager = ConnectionManager::new(policy());
+        manager.start(&mut wifi, networks(), now);
+        manager.poll(&mut wifi, now);
+        assert_eq!(manager.poll(&mut wifi, now), &connected("home"));
+
+        // Changing the settings drops the link before the new attempt
+        wifi.drop_connection();
+        manager.start(&mut wifi, networks(), now);
+
+        assert_eq!(manager.poll(&mut wifi, now), &connected("home"));
+    }
+
+    #[test]
+    fn it_reconnects_after_a_dropped_connection() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home", "office"]);
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), now);
+        manager.poll(&mut wifi, now);
+        assert_eq!(manager.poll(&mut wifi, now), &connected("office"));
+
+        wifi.drop_connection();
+        wifi.in_range.retain(|ap| ap.ssid == "home");
+        wifi.reachable.retain(|ssid| ssid == "home");
+
+        // Tries the same network again while the scan is recent
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("office"));
+
+        let now = now + SCAN_MAX_AGE;
+        manager.poll(&mut wifi, now);
+        let now = now + Duration::from_secs(1);
+        assert_eq!(manager.poll(&mut wifi, now), &ConnectionState::Scanning);
+        assert_eq!(manager.poll(&mut wifi, now), &connecting("home"));
+        assert_eq!(manager.poll(&mut wifi, now), &connected("home"));
+    }
+
+    #[test]
+    fn it_gives_up_after_max_attempts() {
+        let start = Instant::now();
+        let mut wifi = FakeWifi::new(&[]);
+        let mut manager = ConnectionManager::new(RetryPolicy {
+            max_attempts: Some(2),
+            ..policy()
+        });
+
+        manager.start(&mut wifi, networks(), start);
+        manager.poll(&mut wifi, start);
+        let now = start + Duration::from_secs(10);
+        manager.poll(&mut wifi, now);
+        let now = now + Duration::from_secs(1);
+        manager.poll(&mut wifi, now);
+        let now = now + Duration::from_secs(10);
+
+        assert_eq!(manager.poll(&mut wifi, now), &ConnectionState::Failed);
+        assert_eq!(
+            manager.poll(&mut wifi, now + Duration::from_secs(60)),
+            &ConnectionState::Failed
+        );
+        assert_eq!(wifi.connects.len(), 2);
+    }
+
+    #[test]
+    fn it_fails_without_networks() {
+        let mut wifi = FakeWifi::new(&["home"]);
+        let mut manager = ConnectionManager::new(policy());
+
+        let now = Instant::now();
+        manager.start(&mut wifi, Vec::new(), now);
+
+        assert_eq!(manager.poll(&mut wifi, now), &ConnectionState::Failed);
+    }
+
+    #[test]
+    fn it_stops() {
+        let now = Instant::now();
+        let mut wifi = FakeWifi::new(&["home"]);
+        let mut manager = ConnectionManager::new(policy());
+
+        manager.start(&mut wifi, networks(), now);
+        manager.poll(&mut wifi, now);
+        manager.poll(&mut wifi, now);
+        manager.stop(&mut wifi);
+
+        assert_eq!(manager.state(), &ConnectionState::Idle);
+        assert_eq!(wifi.connected_to, None);
+    }
+
+    #[test]
+    fn it_caps_the_backoff() {
+        let policy = policy();
+
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(40), Duration::from_secs(8));
+    }
+}
@@ -0,0 +1,317 @@
+//! Trial WiFi Changes
+//!
+//! New network settings are only kept once the clock manages to connect with
+//! them. Until then the last settings that worked are held on to, and they
+//! are restored if the new ones fail or don't connect in time, so a mistyped
+//! password can't leave the clock stranded.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::ConnectionState;
+use crate::config::InternalConfig;
+
+/// Outcome of the most recent change to the network settings.
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeResult {
+    /// Still waiting to connect with the new settings.
+    Pending,
+    Applied,
+    /// The new settings didn't connect and the previous ones were restored.
+    RolledBack,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TrialDecision {
+    Wait,
+    Keep,
+    RollBack,
+}
+
+pub struct CredentialTrial {
+    previous: InternalConfig,
+    deadline: Instant,
+    /// Whether the connection has restarted with the settings on trial.
+    /// Until it has, the connection state still describes the old ones.
+    restarted: bool,
+}
+
+impl CredentialTrial {
+    /// Starts a trial of new settings if there is something worth going back
+    /// to: settings that were connected, or those already on trial.
+    pub fn begin(
+        current: &InternalConfig,
+        state: &ConnectionState,
+        running: Option<CredentialTrial>,
+        timeout: Duration,
+        now: Instant,
+    ) -> Option<CredentialTrial> {
+        let previous = match (running, state) {
+            (Some(trial), _) => trial.previous,
+            (None, ConnectionState::Connected { .. }) => current.clone(),
+            (None, _) => return None,
+        };
+
+        Some(CredentialTrial {
+            previous,
+            deadline: now + timeout,
+            restarted: false,
+        })
+    }
+
+    /// Call once the connection has been restarted with the settings on
+    /// trial. Only connections made after that count.
+    pub fn connection_restarted(&mut self) {
+        self.restarted = true;
+    }
+
+    pub fn decide(&self, state: &ConnectionState, now: Instant) -> TrialDecision {
+        if !self.restarted {
+            return TrialDecision::Wait;
+        }
+        match state {
+            ConnectionState::Connected { .. } => TrialDecision::Keep,
+            ConnectionState::Failed => TrialDecision::RollBack,
+            _ if now >= self.deadline => TrialDecision::RollBack,
+            _ => TrialDecision::Wait,
+        }
+    }
+
+    /// Returns `config` with the network settings that last worked.
+    pub fn restore(self, config: &InternalConfig) -> InternalConfig {
+        config.clone().with_network_settings_of(&self.previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wifi::{
+        ConnectionManager, KnownNetwork, LinkEvent, RetryPolicy, ScanResult, WifiDriver,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn connected() -> ConnectionState {
+        ConnectionState::Connected {
+            ssid: String::from("home"),
+        }
+    }
+
+    fn connecting() -> ConnectionState {
+        ConnectionState::Connecting {
+            ssid: String::from("home"),
+        }
+    }
+
+    fn started(trial: Option<CredentialTrial>) -> CredentialTrial {
+        let mut trial = trial.unwrap();
+        trial.connection_restarted();
+        trial
+    }
+
+    fn working() -> InternalConfig {
+        InternalConfig::new("home", "right-pass", "US/Central", 0x123456, false)
+    }
+
+    #[test]
+    fn it_skips_the_trial_when_nothing_was_working() {
+        let now = Instant::now();
+
+        for state in [
+            ConnectionState::Idle,
+            connecting(),
+            ConnectionState::Backoff { failures: 1 },
+            ConnectionState::Failed,
+        ] {
+            assert!(CredentialTrial::begin(&working(), &state, None, TIMEOUT, now).is_none());
+        }
+    }
+
+    #[test]
+    fn it_waits_for_the_connection_to_restart() {
+        let now = Instant::now();
+        let trial = CredentialTrial::begin(&working(), &connected(), None, TIMEOUT, now).unwrap();
+
+        // Still connected with the old settings
+        assert_eq!(trial.decide(&connected(), now), TrialDecision::Wait);
+    }
+
+    #[test]
+    fn it_keeps_settings_that_connect() {
+        let now = Instant::now();
+        let trial = started(CredentialTrial::begin(
+            &working(),
+            &connected(),
+            None,
+            TIMEOUT,
+            now,
+        ));
+
+        assert_eq!(trial.decide(&connecting(), now), TrialDecision::Wait);
+        assert_eq!(
+            trial.decide(&connected(), now + Duration::from_secs(10)),
+            TrialDecision::Keep
+        );
+    }
+
+    #[test]
+    fn it_waits_through_retries_until_the_timeout() {
+        let now = Instant::now();
+        let trial = started(CredentialTrial::begin(
+            &working(),
+            &connected(),
+            None,
+            TIMEOUT,
+            now,
+        ));
+        let backoff = ConnectionState::Backoff { failures: 2 };
+
+        assert_eq!(
+            trial.decide(&backoff, now + Duration::from_secs(59)),
+            TrialDecision::Wait
+        );
+        assert_eq!(
+            trial.decide(&backoff, now + TIMEOUT),
+            TrialDecision::RollBack
+        );
+    }
+
+    #[test]
+    fn it_rolls_back_when_the_connection_fails() {
+        let now = Instant::now();
+        let trial = started(CredentialTrial::begin(
+            &working(),
+            &connected(),
+            None,
+            TIMEOUT,
+            now,
+        ));
+
+        assert_eq!(
+            trial.decide(&ConnectionState::Failed, now),
+            TrialDecision::RollBack
+        );
+    }
+
+    #[test]
+    fn it_restores_only_the_network_settings() {
+        let now = Instant::now();
+        let trial = started(CredentialTrial::begin(
+            &working(),
+            &connected(),
+            None,
+            TIMEOUT,
+            now,
+        ));
+
+        let changed = InternalConfig::new("home", "wrong-pass", "US/Eastern", 0xff0000, true)
+            .with_networks(vec![KnownNetwork::new("office", "pass", 1)]);
+
+        assert_eq!(
+            trial.restore(&changed),
+            InternalConfig::new("home", "right-pass", "US/Eastern", 0xff0000, true)
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_last_working_settings_across_changes() {
+        let now = Instant::now();
+        let trial = started(CredentialTrial::begin(
+            &working(),
+            &connected(),
+            None,
+            TIMEOUT,
+            now,
+        ));
+
+        let first = InternalConfig::new("home", "wrong-pass", "US/Central", 0x123456, false);
+        let later = now + Duration::from_secs(30);
+        let trial = started(CredentialTrial::begin(
+            &first,
+            &connecting(),
+            Some(trial),
+            TIMEOUT,
+            later,
+        ));
+
+        let second = InternalConfig::new("home", "also-wrong", "US/Central", 0x123456, false);
+        assert_eq!(
+            trial.decide(&connecting(), now + TIMEOUT),
+            TrialDecision::Wait
+        );
+        assert_eq!(trial.restore(&second), working());
+    }
+
+    /// Finds nothing when scanning and joins "home" only with the right
+    /// password, on the poll after `connect`.
+    #[derive(Default)]
+    struct Wifi {
+        scanning: bool,
+        events: Vec<LinkEvent>,
+    }
+
+    impl WifiDriver for Wifi {
+        type Error = &'static str;
+
+        fn start_scan(&mut self) -> Result<(), Self::Error> {
+            self.scanning = true;
+            Ok(())
+        }
+
+        fn scan_results(&mut self) -> Option<Result<Vec<ScanResult>, Self::Error>> {
+            std::mem::take(&mut self.scanning).then(|| Ok(Vec::new()))
+        }
+
+        fn connect(&mut self, network: &KnownNetwork) -> Result<(), Self::Error> {
+            if network.pass == "right-pass" {
+                self.events.push(LinkEvent::Up);
+            }
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn next_event(&mut self) -> Option<LinkEvent> {
+            self.events.pop()
+        }
+    }
+
+    /// Changes the settings the way the main loop does: begin the trial,
+    /// decide, restart the connection, then poll and decide on later passes.
+    fn change_settings(pass: &str) -> TrialDecision {
+        let start = Instant::now();
+        let mut wifi = Wifi::default();
+        let mut connection = ConnectionManager::new(RetryPolicy::default());
+        connection.start(&mut wifi, working().candidate_networks(), start);
+        connection.poll(&mut wifi, start);
+        assert_eq!(connection.poll(&mut wifi, start), &connected());
+
+        let changed = InternalConfig::new("home", pass, "US/Central", 0x123456, false);
+        let mut trial =
+            CredentialTrial::begin(&working(), connection.state(), None, TIMEOUT, start).unwrap();
+        assert_eq!(trial.decide(connection.state(), start), TrialDecision::Wait);
+        connection.start(&mut wifi, changed.candidate_networks(), start);
+        trial.connection_restarted();
+
+        let mut now = start;
+        loop {
+            now += Duration::from_millis(200);
+            let state = connection.poll(&mut wifi, now);
+            match trial.decide(state, now) {
+                TrialDecision::Wait => continue,
+                decision => return decision,
+            }
+        }
+    }
+
+    #[test]
+    fn it_decides_in_main_loop_order() {
+        assert_eq!(change_settings("right-pass"), TrialDecision::Keep);
+        assert_eq!(change_settings("wrong-pass"), TrialDecision::RollBack);
+    }
+}